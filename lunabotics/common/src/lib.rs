@@ -9,8 +9,10 @@ pub const THALASSIC_CELL_COUNT: u32 = 128 * 256;
 #[cfg(feature = "godot_urdf")]
 pub mod godot_urdf;
 pub mod lunasim;
+pub mod sequence;
 #[cfg(feature = "thalassic")]
 pub mod thalassic;
+pub mod units;
 
 #[derive(Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
 pub enum LunabotStage {
@@ -21,6 +23,15 @@ pub enum LunabotStage {
     Dump,
 }
 
+/// How a message must be sent over cakap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reliability {
+    /// Telemetry that is superseded by the next message of its kind, so it is fine to drop.
+    Unreliable,
+    /// Commands that must arrive.
+    Reliable,
+}
+
 #[derive(Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
 pub enum FromLunabase {
     Pong,
@@ -28,9 +39,23 @@ pub enum FromLunabase {
     Steering(Steering),
     TraverseObstacles,
     SoftStop,
+    /// Sent by the lunabase when it has moved to a different address, such as another
+    /// interface, so that the lunabot sends to the new address from then on.
+    LunabaseAddress { ip: [u8; 4], port: u16 },
 }
 
 impl FromLunabase {
+    pub fn reliability(&self) -> Reliability {
+        match self {
+            FromLunabase::Pong => Reliability::Unreliable,
+            FromLunabase::ContinueMission
+            | FromLunabase::Steering(_)
+            | FromLunabase::TraverseObstacles
+            | FromLunabase::SoftStop
+            | FromLunabase::LunabaseAddress { .. } => Reliability::Reliable,
+        }
+    }
+
     fn write_code(&self, mut w: impl Write) -> std::io::Result<()> {
         let bytes = bitcode::encode(self);
         write!(w, "{self:?} = 0x")?;
@@ -50,12 +75,30 @@ impl FromLunabase {
     }
 }
 
-#[derive(Debug, Encode, Decode, Clone, PartialEq, Eq)]
+/// A reading of the robot's battery.
+#[derive(Debug, Encode, Decode, Clone, Copy, PartialEq)]
+pub struct PowerStatus {
+    /// Volts across the battery.
+    pub voltage: f32,
+    /// Amps drawn from the battery.
+    pub current: f32,
+    /// The estimated charge remaining, from 0 to 100.
+    pub percent: u8,
+}
+
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
 pub enum FromLunabot {
     Ping(LunabotStage),
+    PowerStatus(PowerStatus),
 }
 
 impl FromLunabot {
+    pub fn reliability(&self) -> Reliability {
+        match self {
+            FromLunabot::Ping(_) | FromLunabot::PowerStatus(_) => Reliability::Unreliable,
+        }
+    }
+
     fn write_code(&self, mut w: impl Write) -> std::io::Result<()> {
         let bytes = bitcode::encode(self);
         write!(w, "{self:?} = 0x")?;
@@ -133,7 +176,36 @@ impl Default for Steering {
 
 #[cfg(test)]
 mod tests {
-    use super::Steering;
+    use super::{FromLunabase, FromLunabot, LunabotStage, PowerStatus, Reliability, Steering};
+
+    #[test]
+    fn power_status01() {
+        let msg = FromLunabot::PowerStatus(PowerStatus {
+            voltage: 24.6,
+            current: 12.5,
+            percent: 87,
+        });
+        assert_eq!(msg.reliability(), Reliability::Unreliable);
+        let bytes = bitcode::encode(&msg);
+        assert_eq!(bitcode::decode::<FromLunabot>(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn reliability01() {
+        assert_eq!(FromLunabase::Pong.reliability(), Reliability::Unreliable);
+        assert_eq!(
+            FromLunabot::Ping(LunabotStage::TeleOp).reliability(),
+            Reliability::Unreliable
+        );
+        for msg in [
+            FromLunabase::ContinueMission,
+            FromLunabase::Steering(Steering::default()),
+            FromLunabase::TraverseObstacles,
+            FromLunabase::SoftStop,
+        ] {
+            assert_eq!(msg.reliability(), Reliability::Reliable, "{msg:?}");
+        }
+    }
 
     #[test]
     fn left_right01() {
@@ -21,6 +21,8 @@ pub enum FromLunasim {
 
 #[derive(Debug, Encode, Decode, Clone)]
 pub enum FromLunasimbot {
+    /// Projected depth points, in the frame selected by lunabot's `point_cloud_frame` option.
+    /// This is the global frame by default.
     PointCloud(Box<[[f32; 3]]>),
     Path(Box<[[f32; 3]]>),
     Thalassic {
@@ -0,0 +1,211 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcode::{Decode, DecodeOwned, Encode};
+
+/// The version of the [`Sequenced`] wire format.
+///
+/// Messages that do not decode as this version are decoded as plain, unsequenced messages,
+/// so that peers that do not sequence their messages (and the code sheets) still work.
+pub const SEQUENCED_VERSION: u8 = 2;
+
+/// How many of the most recent sequence numbers are remembered to detect duplicates.
+const DEDUP_WINDOW: u32 = 64;
+
+/// A message along with its position in the order it was sent.
+#[derive(Debug, Encode, Decode, Clone, PartialEq, Eq)]
+pub struct Sequenced<T> {
+    pub version: u8,
+    /// Identifies the [`SequenceCounter`] that numbered this message, which changes whenever
+    /// the sender restarts.
+    pub session: u32,
+    pub seq: u32,
+    pub msg: T,
+}
+
+/// The position of a received message in the order it was sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceId {
+    pub session: u32,
+    pub seq: u32,
+}
+
+/// Assigns increasing sequence numbers to sent messages.
+#[derive(Debug)]
+pub struct SequenceCounter {
+    session: u32,
+    next: u32,
+}
+
+impl Default for SequenceCounter {
+    /// Starts a counter with a session taken from the clock, so that it differs from the
+    /// session of the counter before a restart.
+    fn default() -> Self {
+        let session = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.subsec_nanos() ^ since.as_secs() as u32)
+            .unwrap_or_default();
+        Self::new(session)
+    }
+}
+
+impl SequenceCounter {
+    pub fn new(session: u32) -> Self {
+        Self { session, next: 0 }
+    }
+
+    pub fn session(&self) -> u32 {
+        self.session
+    }
+
+    pub fn wrap<T>(&mut self, msg: T) -> Sequenced<T> {
+        let seq = self.next;
+        self.next = self.next.wrapping_add(1);
+        Sequenced {
+            version: SEQUENCED_VERSION,
+            session: self.session,
+            seq,
+            msg,
+        }
+    }
+}
+
+/// How a received sequence number relates to the ones received before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arrival {
+    /// Newer than every message received so far.
+    New,
+    /// Older than a message that was already received, but not seen before.
+    Late,
+    /// Already received.
+    Duplicate,
+}
+
+/// Remembers recently received sequence numbers to detect duplicated and reordered messages.
+#[derive(Debug, Default)]
+pub struct DedupWindow {
+    session: Option<u32>,
+    newest: Option<u32>,
+    /// Bit `n` is set if `newest - n` has been received.
+    seen: u64,
+}
+
+impl DedupWindow {
+    /// Records the given sequence number and returns how it arrived.
+    ///
+    /// A message from a different session comes from a peer that restarted, so it starts a
+    /// new window. So does a sequence number too far behind the newest to be remembered.
+    pub fn check(&mut self, SequenceId { session, seq }: SequenceId) -> Arrival {
+        if self.session != Some(session) {
+            self.session = Some(session);
+            self.newest = None;
+        }
+        let Some(newest) = self.newest else {
+            self.newest = Some(seq);
+            self.seen = 1;
+            return Arrival::New;
+        };
+        let ahead = seq.wrapping_sub(newest) as i32;
+        if ahead > 0 {
+            self.seen = self.seen.checked_shl(ahead as u32).unwrap_or(0) | 1;
+            self.newest = Some(seq);
+            return Arrival::New;
+        }
+        let behind = ahead.unsigned_abs();
+        if behind >= DEDUP_WINDOW {
+            self.newest = Some(seq);
+            self.seen = 1;
+            return Arrival::New;
+        }
+        let bit = 1 << behind;
+        if self.seen & bit != 0 {
+            Arrival::Duplicate
+        } else {
+            self.seen |= bit;
+            Arrival::Late
+        }
+    }
+}
+
+/// Decodes a message that may or may not be [`Sequenced`], returning its sequence number if
+/// it has one.
+pub fn decode_maybe_sequenced<T: DecodeOwned>(
+    buffer: &mut bitcode::Buffer,
+    bytes: &[u8],
+) -> Result<(Option<SequenceId>, T), bitcode::Error> {
+    if let Ok(sequenced) = buffer.decode::<Sequenced<T>>(bytes) {
+        if sequenced.version == SEQUENCED_VERSION {
+            let id = SequenceId {
+                session: sequenced.session,
+                seq: sequenced.seq,
+            };
+            return Ok((Some(id), sequenced.msg));
+        }
+    }
+    buffer.decode(bytes).map(|msg| (None, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{FromLunabase, Steering};
+
+    use super::{decode_maybe_sequenced, Arrival, DedupWindow, SequenceCounter, SequenceId};
+
+    fn id(seq: u32) -> SequenceId {
+        SequenceId { session: 0, seq }
+    }
+
+    #[test]
+    fn dedup_window() {
+        let mut window = DedupWindow::default();
+        assert_eq!(window.check(id(0)), Arrival::New);
+        assert_eq!(window.check(id(0)), Arrival::Duplicate);
+        assert_eq!(window.check(id(3)), Arrival::New);
+        assert_eq!(window.check(id(1)), Arrival::Late);
+        assert_eq!(window.check(id(1)), Arrival::Duplicate);
+        assert_eq!(window.check(id(2)), Arrival::Late);
+        assert_eq!(window.check(id(3)), Arrival::Duplicate);
+        assert_eq!(window.check(id(4)), Arrival::New);
+
+        // Sequence numbers wrap around
+        let mut window = DedupWindow::default();
+        assert_eq!(window.check(id(u32::MAX)), Arrival::New);
+        assert_eq!(window.check(id(0)), Arrival::New);
+        assert_eq!(window.check(id(u32::MAX)), Arrival::Duplicate);
+
+        // A count that jumps too far back starts over
+        let mut window = DedupWindow::default();
+        assert_eq!(window.check(id(1000)), Arrival::New);
+        assert_eq!(window.check(id(0)), Arrival::New);
+        assert_eq!(window.check(id(0)), Arrival::Duplicate);
+
+        // A restarted peer starts a new session, even before its count would have left the
+        // window
+        let mut window = DedupWindow::default();
+        assert_eq!(window.check(id(0)), Arrival::New);
+        assert_eq!(window.check(id(1)), Arrival::New);
+        let restarted = |seq| SequenceId { session: 1, seq };
+        assert_eq!(window.check(restarted(0)), Arrival::New);
+        assert_eq!(window.check(restarted(1)), Arrival::New);
+        assert_eq!(window.check(restarted(1)), Arrival::Duplicate);
+    }
+
+    #[test]
+    fn sequenced_and_plain_messages_decode() {
+        let mut buffer = bitcode::Buffer::new();
+        let mut counter = SequenceCounter::new(7);
+        let msg = FromLunabase::Steering(Steering::new(1.0, 0.0));
+
+        let _ = counter.wrap(FromLunabase::Pong);
+        let bytes = bitcode::encode(&counter.wrap(msg));
+        assert_eq!(
+            decode_maybe_sequenced::<FromLunabase>(&mut buffer, &bytes).unwrap(),
+            (Some(SequenceId { session: 7, seq: 1 }), msg)
+        );
+
+        let bytes = bitcode::encode(&msg);
+        assert_eq!(
+            decode_maybe_sequenced::<FromLunabase>(&mut buffer, &bytes).unwrap(),
+            (None, msg)
+        );
+    }
+}
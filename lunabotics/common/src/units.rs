@@ -0,0 +1,142 @@
+//! Lightweight wrappers around scalars that give them a unit, so that mixing up units is a
+//! compile error instead of a bug.
+//!
+//! ```compile_fail
+//! use common::units::{Degrees, Radians};
+//!
+//! fn turn(angle: Radians<f64>) {}
+//!
+//! turn(Degrees(90.0));
+//! ```
+//!
+//! ```
+//! use common::units::{Degrees, Radians};
+//!
+//! fn turn(angle: Radians<f64>) {}
+//!
+//! turn(Degrees(90.0).into());
+//! ```
+use std::{
+    ops::{Add, Div, Mul, Neg, Sub},
+    time::Duration,
+};
+
+macro_rules! unit {
+    ($(#[$meta:meta])* $name: ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+        pub struct $name<N>(pub N);
+
+        impl<N: Add<Output = N>> Add for $name<N> {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl<N: Sub<Output = N>> Sub for $name<N> {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+
+        impl<N: Neg<Output = N>> Neg for $name<N> {
+            type Output = Self;
+
+            fn neg(self) -> Self {
+                Self(-self.0)
+            }
+        }
+
+        impl<N: Mul<Output = N>> Mul<N> for $name<N> {
+            type Output = Self;
+
+            fn mul(self, rhs: N) -> Self {
+                Self(self.0 * rhs)
+            }
+        }
+
+        impl<N: Div<Output = N>> Div<N> for $name<N> {
+            type Output = Self;
+
+            fn div(self, rhs: N) -> Self {
+                Self(self.0 / rhs)
+            }
+        }
+    };
+}
+
+unit!(
+    /// A distance in meters.
+    Meters
+);
+unit!(
+    /// An angle in radians.
+    Radians
+);
+unit!(
+    /// An angle in degrees.
+    Degrees
+);
+unit!(
+    /// A speed in meters per second.
+    MetersPerSecond
+);
+
+macro_rules! float_conversions {
+    ($float: ident, $as_secs: ident) => {
+        impl From<Degrees<$float>> for Radians<$float> {
+            fn from(value: Degrees<$float>) -> Self {
+                Self(value.0.to_radians())
+            }
+        }
+
+        impl From<Radians<$float>> for Degrees<$float> {
+            fn from(value: Radians<$float>) -> Self {
+                Self(value.0.to_degrees())
+            }
+        }
+
+        impl Div<Duration> for Meters<$float> {
+            type Output = MetersPerSecond<$float>;
+
+            fn div(self, rhs: Duration) -> MetersPerSecond<$float> {
+                MetersPerSecond(self.0 / rhs.$as_secs())
+            }
+        }
+
+        impl Mul<Duration> for MetersPerSecond<$float> {
+            type Output = Meters<$float>;
+
+            fn mul(self, rhs: Duration) -> Meters<$float> {
+                Meters(self.0 * rhs.$as_secs())
+            }
+        }
+    };
+}
+
+float_conversions!(f32, as_secs_f32);
+float_conversions!(f64, as_secs_f64);
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Degrees, Meters, MetersPerSecond, Radians};
+
+    #[test]
+    fn conversions01() {
+        let angle: Radians<f64> = Degrees(180.0).into();
+        assert!((angle.0 - std::f64::consts::PI).abs() < 1e-12);
+        let angle: Degrees<f32> = Radians(std::f32::consts::FRAC_PI_2).into();
+        assert!((angle.0 - 90.0).abs() < 1e-4);
+
+        let speed = Meters(3.0) / Duration::from_millis(1500);
+        assert_eq!(speed, MetersPerSecond(2.0));
+        assert_eq!(speed * Duration::from_secs(2), Meters(4.0));
+        assert_eq!(Meters(1.0) + Meters(0.5) * 2.0, Meters(2.0));
+    }
+}
@@ -15,7 +15,10 @@ use cakap2::{
     packet::{Action, ReliableIndex},
     Event, PeerStateMachine, RecommendedAction,
 };
-use common::{FromLunabase, FromLunabot, LunabotStage, Steering};
+use common::{
+    sequence::{decode_maybe_sequenced, Arrival, DedupWindow, SequenceCounter},
+    FromLunabase, FromLunabot, LunabotStage, Reliability, Steering,
+};
 use godot::{
     classes::{image::Format, Engine, Image},
     prelude::*,
@@ -81,6 +84,8 @@ struct LunabotConnInner {
     udp: UdpSocket,
     to_lunabot: VecDeque<Action>,
     bitcode_buffer: bitcode::Buffer,
+    sequence: SequenceCounter,
+    dedup: DedupWindow,
     did_reconnection: bool,
     last_steering: Option<(Steering, ReliableIndex)>,
     send_to: Option<SocketAddr>,
@@ -155,6 +160,8 @@ impl INode for LunabotConn {
                 cakap_sm,
                 to_lunabot: VecDeque::new(),
                 bitcode_buffer: bitcode::Buffer::new(),
+                sequence: SequenceCounter::default(),
+                dedup: DedupWindow::default(),
                 did_reconnection: false,
                 last_steering: None,
                 send_to: None,
@@ -219,6 +226,17 @@ impl INode for LunabotConn {
                                 ));
                             });
                         }
+                        FromLunabot::PowerStatus(status) => {
+                            self.base_mut().emit_signal(
+                                "power_status",
+                                &[
+                                    status.voltage.to_variant(),
+                                    status.current.to_variant(),
+                                    status.percent.to_variant(),
+                                ],
+                            );
+                            inner = self.inner.as_mut().unwrap();
+                        }
                     }
                 }};
             }
@@ -230,9 +248,11 @@ impl INode for LunabotConn {
                             godot_error!("{cakap_error}")
                         }
                         RecommendedAction::HandleData(received) => {
-                            match inner.bitcode_buffer.decode::<FromLunabot>(received) {
-                                Ok(x) => {
-                                    on_msg!(x);
+                            match decode_maybe_sequenced::<FromLunabot>(&mut inner.bitcode_buffer, received) {
+                                Ok((seq, x)) => {
+                                    if seq.map(|seq| inner.dedup.check(seq)) != Some(Arrival::Duplicate) {
+                                        on_msg!(x);
+                                    }
                                 }
                                 Err(e) => {
                                     godot_error!("Failed to decode message: {e}")
@@ -240,13 +260,15 @@ impl INode for LunabotConn {
                             }
                         }
                         RecommendedAction::HandleDataAndSend { received, to_send } => {
-                            match inner.bitcode_buffer.decode::<FromLunabot>(received) {
-                                Ok(x) => {
+                            match decode_maybe_sequenced::<FromLunabot>(&mut inner.bitcode_buffer, received) {
+                                Ok((seq, x)) => {
                                     if let Some(addr) = inner.send_to {
                                         if let Err(e) = inner.udp.send_to(&to_send, addr) {
                                             godot_error!("Failed to send ack: {e}");
                                         }
-                                        on_msg!(x);
+                                        if seq.map(|seq| inner.dedup.check(seq)) != Some(Arrival::Duplicate) {
+                                            on_msg!(x);
+                                        }
                                     }
                                 }
                                 Err(e) => {
@@ -323,18 +345,25 @@ impl INode for LunabotConn {
 }
 
 impl LunabotConn {
-    fn send_reliable(&mut self, msg: &FromLunabase) {
+    /// Sends a message reliably or not according to its [`Reliability`].
+    fn send(&mut self, msg: &FromLunabase) {
         if let Some(inner) = &mut self.inner {
-            match inner
-                .cakap_sm
-                .get_packet_builder()
-                .new_reliable(encode(msg).into())
-            {
+            let builder = inner.cakap_sm.get_packet_builder();
+            let bytes = encode(&inner.sequence.wrap(*msg));
+            let packet = match msg.reliability() {
+                Reliability::Reliable => builder
+                    .new_reliable(bytes.into())
+                    .map(Action::SendReliable),
+                Reliability::Unreliable => builder
+                    .new_unreliable(bytes.into())
+                    .map(Action::SendUnreliable),
+            };
+            match packet {
                 Ok(packet) => {
-                    inner.to_lunabot.push_back(Action::SendReliable(packet));
+                    inner.to_lunabot.push_back(packet);
                 }
                 Err(e) => {
-                    godot_error!("Failed to build reliable packet: {e}");
+                    godot_error!("Failed to build packet: {e}");
                 }
             }
         }
@@ -350,10 +379,12 @@ impl LunabotConn {
                 }
             }
             let msg = FromLunabase::Steering(new_steering);
+            // Only reliable packets can be cancelled when superseded
+            debug_assert_eq!(msg.reliability(), Reliability::Reliable);
             match inner
                 .cakap_sm
                 .get_packet_builder()
-                .new_reliable(encode(&msg).into())
+                .new_reliable(encode(&inner.sequence.wrap(msg)).into())
             {
                 Ok(packet) => {
                     if let Some(old_idx) = last_steering_reliable_idx {
@@ -368,19 +399,6 @@ impl LunabotConn {
             }
         }
     }
-
-    // fn send_unreliable(&mut self, msg: &FromLunabase) {
-    //     if let Some(inner) = &mut self.inner {
-    //         match inner.cakap_sm.get_packet_builder().new_unreliable(encode(msg).into()) {
-    //             Ok(packet) => {
-    //                 inner.to_lunabot.push_back(Action::SendUnreliable(packet));
-    //             }
-    //             Err(e) => {
-    //                 godot_error!("Failed to build reliable packet: {e}");
-    //             }
-    //         }
-    //     }
-    // }
 }
 
 #[godot_api]
@@ -397,6 +415,8 @@ impl LunabotConn {
     fn entered_dig(&self);
     #[signal]
     fn entered_dump(&self);
+    #[signal]
+    fn power_status(&self, voltage: f32, current: f32, percent: u8);
 
     #[cfg(feature = "production")]
     #[constant]
@@ -424,16 +444,30 @@ impl LunabotConn {
 
     #[func]
     fn continue_mission(&mut self) {
-        self.send_reliable(&FromLunabase::ContinueMission);
+        self.send(&FromLunabase::ContinueMission);
     }
 
     #[func]
     fn traverse_obstacles(&mut self) {
-        self.send_reliable(&FromLunabase::TraverseObstacles);
+        self.send(&FromLunabase::TraverseObstacles);
     }
 
     #[func]
     fn soft_stop(&mut self) {
-        self.send_reliable(&FromLunabase::SoftStop);
+        self.send(&FromLunabase::SoftStop);
+    }
+
+    /// Tells the lunabot to send to the given IPv4 address from now on. Returns `false` if the
+    /// address could not be parsed.
+    #[func]
+    fn announce_address(&mut self, address: GString) -> bool {
+        let Ok(address) = address.to_string().parse::<SocketAddrV4>() else {
+            return false;
+        };
+        self.send(&FromLunabase::LunabaseAddress {
+            ip: address.ip().octets(),
+            port: address.port(),
+        });
+        true
     }
 }
@@ -21,6 +21,8 @@ pub(super) fn dig() -> impl Behavior<LunabotBlackboard> + CancelSafe {
         Sequence::new((
             AssertCancelSafe(|blackboard: &mut LunabotBlackboard| {
                 blackboard.enqueue_action(Action::SetStage(LunabotStage::Dig));
+                // The heightmap is only needed to traverse
+                blackboard.enqueue_action(Action::SetObserveDepth(false));
                 Status::Success
             }),
             AssertCancelSafe(|blackboard: &mut LunabotBlackboard| {
@@ -56,7 +56,7 @@ pub fn autonomy() -> impl Behavior<LunabotBlackboard> {
             AssertCancelSafe(|blackboard: &mut LunabotBlackboard| {
                 if *blackboard.lunabase_disconnected() {
                     error!("Lunabase disconnected");
-                    return Status::Failure;
+                    return Status::failure("lunabase disconnected");
                 }
                 while let Some(msg) = blackboard.peek_from_lunabase() {
                     match msg {
@@ -67,7 +67,7 @@ pub fn autonomy() -> impl Behavior<LunabotBlackboard> {
                         }
                         FromLunabase::SoftStop => {
                             blackboard.pop_from_lunabase();
-                            return Status::Failure;
+                            return Status::failure("soft stop");
                         }
                         _ => blackboard.pop_from_lunabase(),
                     };
@@ -224,3 +224,41 @@ fn scaled_clamp(a: f64, b: f64, bound: f64) -> (f64, f64) {
         ((bound * a / b).abs() * a.signum(), bound * b.signum())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ares_bt::Behavior;
+    use simple_motion::ChainBuilder;
+
+    use crate::{blackboard::LunabotBlackboard, Action};
+
+    use super::{dig, dump, traverse, Autonomy, AutonomyStage};
+
+    /// Runs `behavior` once in the given stage, returning what it set depth observation to.
+    fn observe_depth_in(
+        stage: AutonomyStage,
+        mut behavior: impl Behavior<LunabotBlackboard>,
+    ) -> Option<bool> {
+        let mut blackboard =
+            LunabotBlackboard::new(ChainBuilder::new_free().finish_static().into());
+        *blackboard.get_autonomy() = Autonomy::FullAutonomy(stage);
+        behavior.run(&mut blackboard);
+        blackboard
+            .drain_actions()
+            .filter_map(|action| match action {
+                Action::SetObserveDepth(observe) => Some(observe),
+                _ => None,
+            })
+            .last()
+    }
+
+    #[test]
+    fn depth_is_only_observed_while_traversing() {
+        assert_eq!(observe_depth_in(AutonomyStage::Dig, dig()), Some(false));
+        assert_eq!(observe_depth_in(AutonomyStage::Dump, dump()), Some(false));
+        assert_eq!(
+            observe_depth_in(AutonomyStage::TraverseObstacles, traverse()),
+            Some(true)
+        );
+    }
+}
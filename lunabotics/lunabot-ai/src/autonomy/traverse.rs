@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use ares_bt::{
     action::{AlwaysFail, AlwaysSucceed},
@@ -16,6 +16,97 @@ use crate::{blackboard::LunabotBlackboard, utils::WaitBehavior, Action};
 
 use super::{follow_path, Autonomy, AutonomyStage};
 
+/// Where the robot traverses the obstacles to.
+const TRAVERSE_GOAL: Point3<f64> = Point3::new(-3.0, 0.0, -6.0);
+/// How often the path being followed is checked for newly observed obstacles.
+const PATH_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// How close the robot has to get to the goal to have arrived, in meters.
+const GOAL_TOLERANCE: f64 = 0.3;
+/// How much closer the robot has to get to the goal within [`STUCK_WINDOW`] to not be stuck,
+/// in meters.
+const MIN_PROGRESS: f64 = 0.1;
+const STUCK_WINDOW: Duration = Duration::from_secs(3);
+
+/// Succeeds once the robot is close enough to the goal, and fails if it stops getting closer.
+struct TraverseProgress {
+    goal: Point3<f64>,
+    /// The closest the robot has gotten to the goal, and when it got there.
+    best: Option<(f64, Instant)>,
+}
+
+impl TraverseProgress {
+    fn new(goal: Point3<f64>) -> Self {
+        Self { goal, best: None }
+    }
+
+    /// The distance from the given position to the goal along the ground.
+    fn distance_to_goal(&self, position: Point3<f64>) -> f64 {
+        (position.xz() - self.goal.xz()).magnitude()
+    }
+
+    fn check(&mut self, position: Point3<f64>, now: Instant) -> Status {
+        let distance = self.distance_to_goal(position);
+        if distance <= GOAL_TOLERANCE {
+            self.best = None;
+            return Status::Success;
+        }
+        match self.best {
+            Some((best, since)) if best - distance < MIN_PROGRESS => {
+                if now.duration_since(since) >= STUCK_WINDOW {
+                    self.best = None;
+                    Status::failure("stuck")
+                } else {
+                    Status::Running
+                }
+            }
+            _ => {
+                self.best = Some((distance, now));
+                Status::Running
+            }
+        }
+    }
+}
+
+impl Behavior<LunabotBlackboard> for TraverseProgress {
+    fn run(&mut self, blackboard: &mut LunabotBlackboard) -> Status {
+        let position = blackboard.get_robot_isometry().translation.vector.into();
+        let status = self.check(position, blackboard.get_now());
+        if matches!(status, Status::Failure(_)) {
+            warn!("Stuck while traversing");
+        }
+        status
+    }
+}
+
+impl CancelSafe for TraverseProgress {
+    fn reset(&mut self) {
+        self.best = None;
+    }
+}
+
+/// Fails once the path being followed is found to be blocked, so that a new path can be
+/// calculated.
+fn watch_path() -> impl Behavior<LunabotBlackboard> + CancelSafe {
+    WhileLoop::new(
+        AlwaysSucceed,
+        Sequence::new((
+            WaitBehavior::from(PATH_CHECK_INTERVAL),
+            AssertCancelSafe(|blackboard: &mut LunabotBlackboard| {
+                if blackboard.is_path_blocked() {
+                    warn!("Path is blocked, replanning");
+                    return Status::failure("path blocked");
+                }
+                if let Some(path) = blackboard.get_path() {
+                    let path = path.to_vec();
+                    let from = blackboard.get_robot_isometry().translation.vector.into();
+                    blackboard.enqueue_action(Action::CheckPath { from, path });
+                }
+                Status::Success
+            }),
+        )),
+    )
+}
+
 pub(super) fn traverse() -> impl Behavior<LunabotBlackboard> + CancelSafe {
     IfElse::new(
         AssertCancelSafe(|blackboard: &mut LunabotBlackboard| {
@@ -31,6 +122,7 @@ pub(super) fn traverse() -> impl Behavior<LunabotBlackboard> + CancelSafe {
                 warn!("Traversing obstacles");
                 blackboard.enqueue_action(Action::SetSteering(Default::default()));
                 blackboard.enqueue_action(Action::SetStage(LunabotStage::TraverseObstacles));
+                blackboard.enqueue_action(Action::SetObserveDepth(true));
                 Status::Success
             }),
             WhileLoop::new(
@@ -41,7 +133,7 @@ pub(super) fn traverse() -> impl Behavior<LunabotBlackboard> + CancelSafe {
                             AssertCancelSafe(|blackboard: &mut LunabotBlackboard| {
                                 blackboard.calculate_path(
                                     blackboard.get_robot_isometry().translation.vector.into(),
-                                    Point3::new(-3.0, 0.0, -6.0),
+                                    TRAVERSE_GOAL,
                                 );
                                 Status::Success
                             }),
@@ -55,6 +147,7 @@ pub(super) fn traverse() -> impl Behavior<LunabotBlackboard> + CancelSafe {
                             InfallibleShim(AssertCancelSafe(follow_path)),
                         )),
                         Sequence::new((WaitBehavior::from(Duration::from_secs(4)), AlwaysFail)),
+                        ParallelAny::new((watch_path(), TraverseProgress::new(TRAVERSE_GOAL))),
                     )),
                     AlwaysFail,
                     Sequence::new((
@@ -75,3 +168,47 @@ pub(super) fn traverse() -> impl Behavior<LunabotBlackboard> + CancelSafe {
         AlwaysSucceed,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use ares_bt::Status;
+    use nalgebra::{Point3, Vector3};
+
+    use super::{TraverseProgress, TRAVERSE_GOAL};
+
+    #[test]
+    fn converging_poses_arrive() {
+        let mut progress = TraverseProgress::new(TRAVERSE_GOAL);
+        let start = Instant::now();
+        let mut status = Status::Running;
+        for i in 0..=6 {
+            let position = Point3::new(-3.0, 0.2, -(i as f64));
+            status = progress.check(position, start + Duration::from_secs(i));
+            if i < 6 {
+                assert_eq!(status, Status::Running);
+            }
+        }
+        assert_eq!(status, Status::Success);
+    }
+
+    #[test]
+    fn stalled_poses_are_stuck() {
+        let mut progress = TraverseProgress::new(TRAVERSE_GOAL);
+        let start = Instant::now();
+        let position = Point3::new(0.0, 0.0, 0.0);
+        for i in 0..6 {
+            // Jitters in place without getting closer
+            let position = position + Vector3::new(0.0, 0.0, -0.01 * (i % 2) as f64);
+            assert_eq!(
+                progress.check(position, start + Duration::from_millis(500 * i)),
+                Status::Running
+            );
+        }
+        assert_eq!(
+            progress.check(position, start + Duration::from_secs(3)),
+            Status::failure("stuck")
+        );
+    }
+}
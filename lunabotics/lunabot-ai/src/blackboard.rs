@@ -4,11 +4,13 @@ use common::FromLunabase;
 use nalgebra::{Isometry3, Point3};
 use simple_motion::StaticImmutableNode;
 
-use crate::{autonomy::Autonomy, Action, PollWhen};
+use crate::{autonomy::Autonomy, teleop::SteeringLimiter, Action, PollWhen};
 
 pub enum Input {
     FromLunabase(FromLunabase),
     PathCalculated(Vec<Point3<f64>>),
+    /// The path being followed has been blocked by a newly observed obstacle.
+    PathBlocked,
     LunabaseDisconnected,
 }
 
@@ -18,9 +20,11 @@ pub(crate) struct LunabotBlackboard {
     autonomy: Autonomy,
     chain: StaticImmutableNode,
     path: Vec<Point3<f64>>,
+    path_blocked: bool,
     lunabase_disconnected: bool,
     actions: Vec<Action>,
     poll_when: PollWhen,
+    steering_limiter: SteeringLimiter,
 }
 
 impl LunabotBlackboard {
@@ -30,10 +34,12 @@ impl LunabotBlackboard {
             from_lunabase: Default::default(),
             autonomy: Autonomy::None,
             path: vec![],
+            path_blocked: false,
             chain,
             lunabase_disconnected: true,
             actions: vec![],
             poll_when: PollWhen::NoDelay,
+            steering_limiter: SteeringLimiter::default(),
         }
     }
 }
@@ -55,6 +61,10 @@ impl LunabotBlackboard {
         &mut self.poll_when
     }
 
+    pub fn get_steering_limiter(&mut self) -> &mut SteeringLimiter {
+        &mut self.steering_limiter
+    }
+
     pub fn get_robot_isometry(&self) -> Isometry3<f64> {
         self.chain.get_global_isometry()
     }
@@ -67,6 +77,10 @@ impl LunabotBlackboard {
         }
     }
 
+    pub fn is_path_blocked(&self) -> bool {
+        self.path_blocked
+    }
+
     pub fn lunabase_disconnected(&mut self) -> &mut bool {
         &mut self.lunabase_disconnected
     }
@@ -83,12 +97,14 @@ impl LunabotBlackboard {
         match input {
             Input::FromLunabase(msg) => self.from_lunabase.push_back(msg),
             Input::PathCalculated(path) => self.path = path,
+            Input::PathBlocked => self.path_blocked = true,
             Input::LunabaseDisconnected => self.lunabase_disconnected = true,
         }
     }
 
     pub fn calculate_path(&mut self, from: Point3<f64>, to: Point3<f64>) {
         let into = std::mem::take(&mut self.path);
+        self.path_blocked = false;
         self.enqueue_action(Action::CalculatePath { from, to, into });
     }
 
@@ -1,4 +1,7 @@
-use std::{time::Instant, vec};
+use std::{
+    time::{Duration, Instant},
+    vec,
+};
 
 use ares_bt::{
     action::AlwaysSucceed,
@@ -6,7 +9,7 @@ use ares_bt::{
     converters::{CatchPanic, Invert},
     looping::WhileLoop,
     sequence::Sequence,
-    EternalBehavior, FallibleStatus, InfallibleStatus,
+    Behavior, EternalBehavior, FallibleStatus, InfallibleStatus, Status,
 };
 use autonomy::autonomy;
 use blackboard::LunabotBlackboard;
@@ -14,14 +17,22 @@ use common::{FromLunabase, LunabotStage, Steering};
 use nalgebra::Point3;
 use simple_motion::StaticImmutableNode;
 use teleop::teleop;
-use tracing::warn;
+use tracing::{error, info, warn};
+use utils::TickStats;
+use watchdog::TickWatchdog;
 
 mod autonomy;
 mod blackboard;
+mod recorder;
 mod teleop;
 mod utils;
+mod watchdog;
 
 pub use blackboard::Input;
+pub use recorder::SteeringRecorder;
+
+/// How long a single tick of the behavior tree may take before it is considered stalled.
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone)]
 pub enum Action {
@@ -32,6 +43,16 @@ pub enum Action {
         to: Point3<f64>,
         into: Vec<Point3<f64>>,
     },
+    /// Checks if the given path is still clear of obstacles ahead of the robot at `from`,
+    /// responding with [`Input::PathBlocked`] if it is not.
+    CheckPath {
+        from: Point3<f64>,
+        path: Vec<Point3<f64>>,
+    },
+    /// Pauses or resumes projecting depth frames into the heightmap.
+    ///
+    /// Depth is always observed while calculating a path, regardless of this setting.
+    SetObserveDepth(bool),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -44,12 +65,22 @@ pub enum PollWhen {
     NoDelay,
 }
 
+/// Runs the behavior tree forever.
+///
+/// Steering from the lunabase is applied at most once every `min_steering_interval`.
+///
+/// If running the behavior tree takes longer than a second in one tick, `on_stall` is called
+/// from another thread. It should stop the drive, as the behavior tree cannot. Time spent in
+/// `on_action` does not count towards this.
 pub fn run_ai(
     chain: StaticImmutableNode,
+    min_steering_interval: Duration,
     mut on_action: impl FnMut(Action, &mut Vec<Input>),
     mut polling: impl FnMut(PollWhen, &mut Vec<Input>),
+    on_stall: impl Fn() + Send + 'static,
 ) {
     let mut blackboard = LunabotBlackboard::new(chain);
+    blackboard.get_steering_limiter().min_interval = min_steering_interval;
     let mut b = WhileLoop::new(
         AlwaysSucceed,
         Sequence::new((
@@ -66,7 +97,7 @@ pub fn run_ai(
                             FromLunabase::ContinueMission => {
                                 warn!("Continuing mission");
                                 *blackboard.lunabase_disconnected() = false;
-                                return FallibleStatus::Failure;
+                                return FallibleStatus::FAILURE;
                             }
                             _ => {}
                         }
@@ -77,19 +108,33 @@ pub fn run_ai(
             )),
             // follow_path,
             TryCatch::new(
-                WhileLoop::new(
-                    AlwaysSucceed,
-                    Sequence::new((CatchPanic(teleop()), CatchPanic(autonomy()))),
-                ),
+                {
+                    let mut mission = WhileLoop::new(
+                        AlwaysSucceed,
+                        Sequence::new((CatchPanic(teleop()), CatchPanic(autonomy()))),
+                    );
+                    move |blackboard: &mut LunabotBlackboard| {
+                        let status = mission.run(blackboard);
+                        if let Status::Failure(reason) = status {
+                            error!("Mission failed: {reason}");
+                        }
+                        status
+                    }
+                },
                 AlwaysSucceed,
             ),
         )),
     );
 
     let mut inputs = vec![];
+    let mut tick_stats = TickStats::new(Instant::now());
+    let watchdog = TickWatchdog::spawn(STALL_TIMEOUT, on_stall);
     loop {
+        watchdog.tick_started();
         blackboard.update_now();
         b.run_eternal(&mut blackboard);
+        // Actions such as calculating a path may block for a while, which is not a stall
+        watchdog.tick_finished();
         for action in blackboard.drain_actions() {
             std::thread::sleep(std::time::Duration::from_millis(16));
             on_action(action, &mut inputs);
@@ -97,7 +142,23 @@ pub fn run_ai(
         for input in inputs.drain(..) {
             blackboard.digest_input(input);
         }
-        polling(*blackboard.get_poll_when(), &mut inputs);
+        let poll_when = *blackboard.get_poll_when();
+        polling(poll_when, &mut inputs);
+        if let PollWhen::Instant(deadline) = poll_when {
+            let now = Instant::now();
+            if let Some(lateness) = tick_stats.record(deadline, now) {
+                warn!("Behavior tick overran by {lateness:?}");
+            }
+            if let Some(report) = tick_stats.take_report(now) {
+                info!(
+                    ticks = report.ticks,
+                    overruns = report.overruns,
+                    mean_lateness = ?report.mean_lateness,
+                    max_lateness = ?report.max_lateness,
+                    "Behavior tick timing"
+                );
+            }
+        }
         *blackboard.get_poll_when() = PollWhen::NoDelay;
         for input in inputs.drain(..) {
             blackboard.digest_input(input);
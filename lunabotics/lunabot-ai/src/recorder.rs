@@ -0,0 +1,79 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use common::Steering;
+
+/// The most steering commands a [`SteeringRecorder`] will hold before discarding the oldest.
+const MAX_RECORDED_STEERING: usize = 4096;
+
+/// Records the steering commands produced by the behavior tree, so that a dry run can be
+/// inspected without actuating anything.
+///
+/// Clones share the same history.
+#[derive(Clone, Default)]
+pub struct SteeringRecorder {
+    history: Arc<Mutex<VecDeque<(Instant, Steering)>>>,
+}
+
+impl SteeringRecorder {
+    pub fn record(&self, steering: Steering) {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == MAX_RECORDED_STEERING {
+            history.pop_front();
+        }
+        history.push_back((Instant::now(), steering));
+    }
+
+    /// Removes and returns every recorded command, oldest first.
+    pub fn take(&self) -> Vec<(Instant, Steering)> {
+        self.history.lock().unwrap().drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ares_bt::Behavior;
+    use common::{FromLunabase, Steering};
+    use simple_motion::ChainBuilder;
+
+    use crate::{blackboard::LunabotBlackboard, teleop::teleop, Action, Input};
+
+    use super::SteeringRecorder;
+
+    #[test]
+    fn records_teleop_steering() {
+        let recorder = SteeringRecorder::default();
+        let mut blackboard =
+            LunabotBlackboard::new(ChainBuilder::new_free().finish_static().into());
+        *blackboard.lunabase_disconnected() = false;
+        let mut behavior = teleop();
+
+        let commands = [
+            Steering::new_left_right(1.0, 1.0),
+            Steering::new_left_right(-1.0, 1.0),
+            Steering::default(),
+        ];
+        for steering in commands {
+            blackboard.digest_input(Input::FromLunabase(FromLunabase::Steering(steering)));
+        }
+        for _ in 0..commands.len() + 1 {
+            behavior.run(&mut blackboard);
+            for action in blackboard.drain_actions() {
+                if let Action::SetSteering(steering) = action {
+                    recorder.record(steering);
+                }
+            }
+        }
+
+        let trace: Vec<_> = recorder
+            .take()
+            .into_iter()
+            .map(|(_, steering)| steering)
+            .collect();
+        assert_eq!(trace, commands);
+        assert!(recorder.take().is_empty());
+    }
+}
@@ -1,5 +1,7 @@
+use std::time::{Duration, Instant};
+
 use ares_bt::{sequence::Sequence, Behavior, Status};
-use common::{FromLunabase, LunabotStage};
+use common::{FromLunabase, LunabotStage, Steering};
 use tracing::{error, warn};
 
 use crate::{
@@ -8,6 +10,49 @@ use crate::{
     Action, PollWhen,
 };
 
+/// Limits how often steering from the lunabase is applied, so that a lunabase sending
+/// steering too quickly cannot flood the drive system.
+///
+/// Steering received in between is not dropped outright; the latest command is applied
+/// once the interval has passed.
+#[derive(Debug, Default)]
+pub(crate) struct SteeringLimiter {
+    pub min_interval: Duration,
+    last_applied_at: Option<Instant>,
+    pending: Option<Steering>,
+}
+
+impl SteeringLimiter {
+    /// Returns the steering to apply now, if any.
+    pub fn offer(&mut self, steering: Steering, now: Instant) -> Option<Steering> {
+        self.pending = Some(steering);
+        self.poll(now)
+    }
+
+    /// Returns the pending steering if it can now be applied.
+    pub fn poll(&mut self, now: Instant) -> Option<Steering> {
+        if self
+            .last_applied_at
+            .is_some_and(|last| now < last + self.min_interval)
+        {
+            return None;
+        }
+        let steering = self.pending.take()?;
+        self.last_applied_at = Some(now);
+        Some(steering)
+    }
+
+    /// When the pending steering can be applied, if there is any.
+    pub fn pending_until(&self) -> Option<Instant> {
+        self.pending?;
+        Some(self.last_applied_at? + self.min_interval)
+    }
+
+    pub fn clear(&mut self) {
+        self.pending = None;
+    }
+}
+
 pub fn teleop() -> impl Behavior<LunabotBlackboard> {
     Sequence::new((
         |blackboard: &mut LunabotBlackboard| {
@@ -17,19 +62,31 @@ pub fn teleop() -> impl Behavior<LunabotBlackboard> {
         |blackboard: &mut LunabotBlackboard| {
             if *blackboard.lunabase_disconnected() {
                 error!("Lunabase disconnected");
-                return Status::Failure;
+                blackboard.get_steering_limiter().clear();
+                return Status::failure("lunabase disconnected");
+            }
+            let now = blackboard.get_now();
+            if let Some(steering) = blackboard.get_steering_limiter().poll(now) {
+                blackboard.enqueue_action(Action::SetSteering(steering));
+                return Status::Running;
             }
             while let Some(msg) = blackboard.pop_from_lunabase() {
                 match msg {
                     FromLunabase::Steering(steering) => {
-                        blackboard.enqueue_action(Action::SetSteering(steering));
-                        return Status::Running;
+                        if let Some(steering) =
+                            blackboard.get_steering_limiter().offer(steering, now)
+                        {
+                            blackboard.enqueue_action(Action::SetSteering(steering));
+                            return Status::Running;
+                        }
                     }
                     FromLunabase::SoftStop => {
                         warn!("Received SoftStop");
-                        return Status::Failure;
+                        blackboard.get_steering_limiter().clear();
+                        return Status::failure("soft stop");
                     }
                     FromLunabase::TraverseObstacles => {
+                        blackboard.get_steering_limiter().clear();
                         *blackboard.get_autonomy() =
                             Autonomy::PartialAutonomy(AutonomyStage::TraverseObstacles);
                         return Status::Success;
@@ -37,8 +94,49 @@ pub fn teleop() -> impl Behavior<LunabotBlackboard> {
                     _ => {}
                 }
             }
-            *blackboard.get_poll_when() = PollWhen::ReceivedLunabase;
+            *blackboard.get_poll_when() = match blackboard.get_steering_limiter().pending_until() {
+                Some(deadline) => PollWhen::Instant(deadline),
+                None => PollWhen::ReceivedLunabase,
+            };
             Status::Running
         },
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use common::Steering;
+
+    use super::SteeringLimiter;
+
+    #[test]
+    fn steering_burst_is_throttled() {
+        let mut limiter = SteeringLimiter {
+            min_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let mut applied = vec![];
+        // 10 commands 1ms apart, ending with a stop
+        for i in 0..10 {
+            let now = start + Duration::from_millis(i);
+            let steering = if i == 9 {
+                Steering::default()
+            } else {
+                Steering::new(1.0, i as f64 / 10.0)
+            };
+            applied.extend(limiter.offer(steering, now));
+            applied.extend(limiter.poll(now));
+        }
+        assert_eq!(applied, [Steering::new(1.0, 0.0)]);
+
+        let deadline = limiter.pending_until().unwrap();
+        assert_eq!(deadline, start + Duration::from_millis(20));
+        assert_eq!(limiter.poll(deadline - Duration::from_millis(1)), None);
+        assert_eq!(limiter.poll(deadline), Some(Steering::default()));
+        assert_eq!(limiter.pending_until(), None);
+        assert_eq!(limiter.poll(deadline + Duration::from_secs(1)), None);
+    }
+}
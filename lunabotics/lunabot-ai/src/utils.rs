@@ -4,6 +4,72 @@ use ares_bt::{Behavior, CancelSafe, InfallibleBehavior, InfallibleStatus, Status
 
 use crate::blackboard::LunabotBlackboard;
 
+/// A tick that starts later than this after its deadline is an overrun.
+const TICK_OVERRUN_THRESHOLD: Duration = Duration::from_millis(5);
+/// How often tick statistics are reported.
+const TICK_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Summary of how punctual the behavior loop was since the last report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TickReport {
+    pub ticks: u32,
+    pub overruns: u32,
+    pub mean_lateness: Duration,
+    pub max_lateness: Duration,
+}
+
+/// Measures how late the behavior loop ticks compared to the instant it asked to be polled at.
+pub(crate) struct TickStats {
+    last_report: Instant,
+    ticks: u32,
+    overruns: u32,
+    total_lateness: Duration,
+    max_lateness: Duration,
+}
+
+impl TickStats {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            last_report: now,
+            ticks: 0,
+            overruns: 0,
+            total_lateness: Duration::ZERO,
+            max_lateness: Duration::ZERO,
+        }
+    }
+
+    /// Records a tick that was meant to start at `deadline` and actually started at `now`.
+    ///
+    /// Returns how late the tick was if it was an overrun.
+    pub fn record(&mut self, deadline: Instant, now: Instant) -> Option<Duration> {
+        let lateness = now.saturating_duration_since(deadline);
+        self.ticks += 1;
+        self.total_lateness += lateness;
+        self.max_lateness = self.max_lateness.max(lateness);
+        if lateness > TICK_OVERRUN_THRESHOLD {
+            self.overruns += 1;
+            Some(lateness)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a report of the ticks since the last report if it is time for one.
+    pub fn take_report(&mut self, now: Instant) -> Option<TickReport> {
+        if now.duration_since(self.last_report) < TICK_REPORT_INTERVAL || self.ticks == 0 {
+            return None;
+        }
+        let report = TickReport {
+            ticks: self.ticks,
+            overruns: self.overruns,
+            mean_lateness: self.total_lateness / self.ticks,
+            max_lateness: self.max_lateness,
+        };
+        *self = Self::new(now);
+        Some(report)
+    }
+}
+
 pub struct WaitBehavior {
     pub duration: Duration,
     start_time: Option<Instant>,
@@ -43,3 +109,41 @@ impl Behavior<LunabotBlackboard> for WaitBehavior {
         self.run_infallible(blackboard).into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{TickReport, TickStats, TICK_REPORT_INTERVAL};
+
+    #[test]
+    fn slow_tick_is_an_overrun() {
+        let start = Instant::now();
+        let mut stats = TickStats::new(start);
+        let deadline = start + Duration::from_millis(16);
+
+        assert_eq!(
+            stats.record(deadline, deadline + Duration::from_millis(1)),
+            None
+        );
+        assert_eq!(
+            stats.record(deadline, deadline + Duration::from_millis(40)),
+            Some(Duration::from_millis(40))
+        );
+        // Waking up early is not late
+        assert_eq!(stats.record(deadline, start), None);
+        assert_eq!(stats.take_report(deadline), None);
+
+        let now = start + TICK_REPORT_INTERVAL;
+        assert_eq!(
+            stats.take_report(now),
+            Some(TickReport {
+                ticks: 3,
+                overruns: 1,
+                mean_lateness: Duration::from_millis(41) / 3,
+                max_lateness: Duration::from_millis(40),
+            })
+        );
+        assert_eq!(stats.take_report(now + TICK_REPORT_INTERVAL), None);
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::error;
+
+/// Watches the behavior tree from another thread, and calls `on_stall` if a tick takes too long.
+///
+/// Time spent waiting for input between ticks does not count, so an idle tree never stalls.
+pub(crate) struct TickWatchdog {
+    tick_started: Arc<Mutex<Option<Instant>>>,
+}
+
+impl TickWatchdog {
+    /// `on_stall` is called at most once per tick, from the watchdog thread.
+    pub fn spawn(timeout: Duration, on_stall: impl Fn() + Send + 'static) -> Self {
+        let tick_started = Arc::new(Mutex::new(None::<Instant>));
+        let weak = Arc::downgrade(&tick_started);
+        std::thread::spawn(move || {
+            let mut stall_check = StallCheck::new(timeout);
+            loop {
+                std::thread::sleep(timeout / 4);
+                let Some(tick_started) = weak.upgrade() else {
+                    break;
+                };
+                let tick_started = *tick_started.lock().unwrap();
+                if let Some(elapsed) = stall_check.check(tick_started, Instant::now()) {
+                    error!("Behavior tree has been stuck in a tick for {elapsed:?}");
+                    on_stall();
+                }
+            }
+        });
+        Self { tick_started }
+    }
+
+    pub fn tick_started(&self) {
+        *self.tick_started.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn tick_finished(&self) {
+        *self.tick_started.lock().unwrap() = None;
+    }
+}
+
+/// Decides when a tick has stalled, reporting each stalled tick only once.
+struct StallCheck {
+    timeout: Duration,
+    last_stalled: Option<Instant>,
+}
+
+impl StallCheck {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last_stalled: None,
+        }
+    }
+
+    /// Returns how long the current tick has run for if it has newly stalled.
+    fn check(&mut self, tick_started: Option<Instant>, now: Instant) -> Option<Duration> {
+        let started = tick_started?;
+        let elapsed = now.duration_since(started);
+        if elapsed >= self.timeout && self.last_stalled != Some(started) {
+            self.last_stalled = Some(started);
+            Some(elapsed)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::StallCheck;
+
+    #[test]
+    fn stalled_tick_fires_once() {
+        let timeout = Duration::from_secs(1);
+        let mut check = StallCheck::new(timeout);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Quick ticks and idle time between ticks are fine
+        assert_eq!(check.check(Some(at(0)), at(100)), None);
+        assert_eq!(check.check(None, at(5000)), None);
+
+        assert_eq!(check.check(Some(at(5000)), at(6000)), Some(timeout));
+        assert_eq!(check.check(Some(at(5000)), at(7000)), None);
+
+        assert_eq!(
+            check.check(Some(at(8000)), at(9500)),
+            Some(Duration::from_millis(1500))
+        );
+    }
+}
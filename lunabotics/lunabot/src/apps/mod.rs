@@ -2,14 +2,25 @@
 mod production;
 mod sim;
 
-use std::{fs::File, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    fs::File,
+    net::{SocketAddr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
+};
 
-use common::{FromLunabase, FromLunabot, LunabotStage};
+use common::{
+    sequence::{decode_maybe_sequenced, Arrival, DedupWindow, SequenceId},
+    FromLunabase, FromLunabot, LunabotStage,
+};
 use crossbeam::atomic::AtomicCell;
 #[cfg(feature = "production")]
 pub use production::{dataviz, Apriltag, CameraInfo, DepthCameraInfo, LunabotApp};
-pub use sim::{LunasimStdin, LunasimbotApp};
-use tasker::tokio::sync::{mpsc, watch};
+pub use sim::{LunasimStdin, LunasimbotApp, PointCloudFrame, SimDepthCamera};
+use tasker::{
+    get_tokio_handle,
+    tokio::sync::{mpsc, watch},
+};
 use tracing::error;
 
 use crate::teleop::{LunabaseConn, PacketBuilder};
@@ -18,6 +29,10 @@ pub fn default_max_pong_delay_ms() -> u64 {
     1500
 }
 
+pub fn default_min_steering_interval_ms() -> u64 {
+    20
+}
+
 fn log_teleop_messages() {
     if let Err(e) = File::create("from_lunabase.txt")
         .map(|f| FromLunabase::write_code_sheet(f))
@@ -48,6 +63,19 @@ impl LunabotConnected {
     }
 }
 
+/// Returns `false` if a message from the lunabase was duplicated, or is a steering command that
+/// arrived after a newer message and so is stale.
+fn should_apply(dedup: &mut DedupWindow, seq: Option<SequenceId>, msg: &FromLunabase) -> bool {
+    let Some(seq) = seq else {
+        return true;
+    };
+    match dedup.check(seq) {
+        Arrival::New => true,
+        Arrival::Late => !matches!(msg, FromLunabase::Steering(_)),
+        Arrival::Duplicate => false,
+    }
+}
+
 fn create_packet_builder(
     lunabase_address: SocketAddr,
     lunabot_stage: Arc<AtomicCell<LunabotStage>>,
@@ -59,14 +87,22 @@ fn create_packet_builder(
 ) {
     let (from_lunabase_tx, from_lunabase_rx) = mpsc::unbounded_channel();
     let mut bitcode_buffer = bitcode::Buffer::new();
+    let mut dedup = DedupWindow::default();
     let (pinged_tx, pinged_rx) = std::sync::mpsc::channel::<()>();
+    let (address_tx, mut address_rx) = mpsc::unbounded_channel();
 
     let packet_builder = LunabaseConn {
         lunabase_address,
-        on_msg: move |bytes: &[u8]| match bitcode_buffer.decode(bytes) {
-            Ok(msg) => {
+        on_msg: move |bytes: &[u8]| match decode_maybe_sequenced(&mut bitcode_buffer, bytes) {
+            Ok((seq, msg)) => {
+                if !should_apply(&mut dedup, seq, &msg) {
+                    // The message was understood, it just should not be applied again
+                    return true;
+                }
                 if msg == FromLunabase::Pong {
                     let _ = pinged_tx.send(());
+                } else if let FromLunabase::LunabaseAddress { ip, port } = msg {
+                    let _ = address_tx.send(SocketAddr::V4(SocketAddrV4::new(ip.into(), port)));
                 } else {
                     let _ = from_lunabase_tx.send(msg);
                 }
@@ -81,6 +117,14 @@ fn create_packet_builder(
     }
     .connect_to_lunabase();
 
+    // The lunabase announces its new address over the connection to its old address
+    let address_packet_builder = packet_builder.clone();
+    get_tokio_handle().spawn(async move {
+        while let Some(address) = address_rx.recv().await {
+            address_packet_builder.set_lunabase_address(address);
+        }
+    });
+
     let (connected_tx, connected_rx) = watch::channel(false);
 
     std::thread::spawn(move || loop {
@@ -100,3 +144,73 @@ fn create_packet_builder(
 
     (packet_builder, from_lunabase_rx, connected)
 }
+
+#[cfg(test)]
+mod tests {
+    use common::{
+        sequence::{decode_maybe_sequenced, DedupWindow, SequenceCounter},
+        FromLunabase, Steering,
+    };
+
+    use super::should_apply;
+
+    #[test]
+    fn duplicate_and_reordered_messages_apply_once() {
+        let mut counter = SequenceCounter::default();
+        let forward = Steering::new(1.0, 0.0);
+        let backward = Steering::new(-1.0, 0.0);
+        let packets: Vec<_> = [
+            FromLunabase::Steering(forward),
+            FromLunabase::SoftStop,
+            FromLunabase::Steering(backward),
+        ]
+        .into_iter()
+        .map(|msg| bitcode::encode(&counter.wrap(msg)))
+        .collect();
+
+        let mut buffer = bitcode::Buffer::new();
+        let mut dedup = DedupWindow::default();
+        let mut applied = vec![];
+        // The soft stop is delayed behind the second steering command, and both steering
+        // commands are duplicated
+        for i in [0, 0, 2, 1, 0, 2] {
+            let (seq, msg) = decode_maybe_sequenced(&mut buffer, &packets[i]).unwrap();
+            if should_apply(&mut dedup, seq, &msg) {
+                applied.push(msg);
+            }
+        }
+        assert_eq!(
+            applied,
+            [
+                FromLunabase::Steering(forward),
+                FromLunabase::Steering(backward),
+                FromLunabase::SoftStop,
+            ]
+        );
+
+        // Messages without sequence numbers are always applied
+        let bytes = bitcode::encode(&FromLunabase::Steering(forward));
+        let (seq, msg) = decode_maybe_sequenced(&mut buffer, &bytes).unwrap();
+        assert!(should_apply(&mut dedup, seq, &msg));
+        assert!(should_apply(&mut dedup, seq, &msg));
+    }
+
+    #[test]
+    fn restarted_lunabase_is_not_deduplicated() {
+        let mut buffer = bitcode::Buffer::new();
+        let mut dedup = DedupWindow::default();
+        let mut counter = SequenceCounter::new(0);
+        for _ in 0..3 {
+            let bytes = bitcode::encode(&counter.wrap(FromLunabase::Pong));
+            let (seq, msg) = decode_maybe_sequenced(&mut buffer, &bytes).unwrap();
+            assert!(should_apply(&mut dedup, seq, &msg));
+        }
+
+        // The restarted lunabase counts from 0 again, well within the window
+        let mut counter = SequenceCounter::new(1);
+        let _ = counter.wrap(FromLunabase::Pong);
+        let bytes = bitcode::encode(&counter.wrap(FromLunabase::SoftStop));
+        let (seq, msg) = decode_maybe_sequenced(&mut buffer, &bytes).unwrap();
+        assert!(should_apply(&mut dedup, seq, &msg));
+    }
+}
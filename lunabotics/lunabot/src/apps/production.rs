@@ -1,31 +1,35 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, num::{NonZeroU32, NonZeroUsize}, sync::Arc, time::Duration};
 
-use anyhow::Context;
 use camera::enumerate_cameras;
 use common::LunabotStage;
 use crossbeam::atomic::AtomicCell;
+use calibration::load_extrinsic_corrections;
 use depth::enumerate_depth_cameras;
+use depth_filter::{SpatialFilterParams, TemporalFilterParams};
 use fxhash::FxHashMap;
 use gputter::init_gputter_blocking;
 use lunabot_ai::{run_ai, Action, Input, PollWhen};
-use nalgebra::{Scale3, Transform3};
+use motors::enumerate_motors;
+use nalgebra::{Isometry3, Scale3, Transform3};
 use pathfinding::grid::Grid;
 use serde::Deserialize;
-use simple_motion::{ChainBuilder, NodeSerde};
 use streaming::camera_streaming;
 use tasker::{get_tokio_handle, shared::OwnedData, tokio, BlockOn};
 use tracing::error;
 
 use crate::{
     apps::log_teleop_messages, localization::Localizer, pathfinding::DefaultPathfinder,
-    pipelines::thalassic::ThalassicData,
+    pipelines::thalassic::{set_observe_depth, ThalassicData},
+    robot_layout::{ExpectedJoint, ExpectedLayout, RobotLayout},
 };
 
 use super::create_packet_builder;
 
 mod apriltag;
+mod calibration;
 mod camera;
 mod depth;
+mod depth_filter;
 mod motors;
 mod streaming;
 
@@ -33,6 +37,7 @@ pub mod dataviz;
 // mod audio_streaming;
 
 pub use apriltag::Apriltag;
+use apriltag::{AprilTagPool, DetectorParams};
 
 #[derive(Deserialize, Debug)]
 pub struct CameraInfo {
@@ -40,6 +45,15 @@ pub struct CameraInfo {
     focal_length_x_px: f64,
     focal_length_y_px: f64,
     stream_index: usize,
+    #[serde(default)]
+    exposure: Option<i64>,
+    #[serde(default)]
+    gain: Option<i64>,
+    /// The minimum number of milliseconds between frames checked for apriltags.
+    #[serde(default)]
+    min_apriltag_interval_ms: u64,
+    #[serde(default)]
+    apriltag_params: DetectorParams,
 }
 
 #[derive(Deserialize, Debug)]
@@ -48,6 +62,34 @@ pub struct DepthCameraInfo {
     #[serde(default)]
     ignore_apriltags: bool,
     stream_index: usize,
+    #[serde(default)]
+    apriltag_params: DetectorParams,
+    /// Depths closer than this many meters are ignored.
+    #[serde(default)]
+    min_depth: Option<f32>,
+    /// Depths further than this many meters are ignored.
+    #[serde(default)]
+    max_depth: Option<f32>,
+    /// Only every Nth depth pixel in each dimension is projected. Defaults to 1.
+    #[serde(default)]
+    decimation: Option<NonZeroU32>,
+    /// Smooth depths with the previous frames. Disabled by default.
+    #[serde(default)]
+    temporal_filter: Option<TemporalFilterParams>,
+    /// Only project depths that agree across this many consecutive frames,
+    /// averaging them. Disabled by default.
+    #[serde(default)]
+    temporal_filter_frames: Option<NonZeroUsize>,
+    /// Smooth depths with their neighbours while preserving edges. Disabled by default.
+    #[serde(default)]
+    spatial_filter: Option<SpatialFilterParams>,
+    /// Fill pixels with no depth from their left neighbour.
+    #[serde(default)]
+    fill_holes: bool,
+    /// The most projected frames that can wait for the thalassic pipeline before the oldest
+    /// is dropped. Defaults to 1.
+    #[serde(default)]
+    max_in_flight_frames: Option<NonZeroUsize>,
 }
 
 fn subaddress_of(mut addr: SocketAddr, port_offset: u16) -> SocketAddr {
@@ -65,10 +107,14 @@ pub struct LunabotApp {
     #[cfg(feature = "experimental")]
     pub lunabase_audio_streaming_address: Option<SocketAddr>,
     pub max_pong_delay_ms: u64,
+    /// The least time between applying steering commands from the lunabase.
+    pub min_steering_interval_ms: u64,
     pub cameras: FxHashMap<String, CameraInfo>,
     pub depth_cameras: FxHashMap<String, DepthCameraInfo>,
     pub apriltags: FxHashMap<String, Apriltag>,
+    pub apriltag_workers: NonZeroUsize,
     pub robot_layout: String,
+    pub extrinsic_calibration: Option<String>,
 }
 
 impl LunabotApp {
@@ -93,11 +139,33 @@ impl LunabotApp {
         let handle = get_tokio_handle();
         let _guard = handle.enter();
 
-        let robot_chain = NodeSerde::from_reader(
-            std::fs::File::open(self.robot_layout).expect("Failed to read robot chain"),
-        )
-        .expect("Failed to parse robot chain");
-        let robot_chain = ChainBuilder::from(robot_chain).finish_static();
+        let robot_layout = match RobotLayout::load(self.robot_layout) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("{e:#}");
+                return;
+            }
+        };
+        let expected_layout = ExpectedLayout {
+            // The localizer sets the isometry of the root
+            root: ExpectedJoint::FREE,
+            links: self
+                .cameras
+                .values()
+                .map(|camera| camera.link_name.as_str())
+                .chain(
+                    self.depth_cameras
+                        .values()
+                        .map(|camera| camera.link_name.as_str()),
+                )
+                .map(|link_name| (link_name, ExpectedJoint::ANY))
+                .collect(),
+        };
+        if let Err(e) = robot_layout.validate(&expected_layout) {
+            error!("{e:#}");
+            return;
+        }
+        let robot_chain = robot_layout.chain;
 
         let localizer = Localizer::new(robot_chain.clone(), None);
         let localizer_ref = localizer.get_ref();
@@ -108,6 +176,8 @@ impl LunabotApp {
 
         camera_streaming(camera_streaming_address);
 
+        let apriltag_pool = AprilTagPool::new(self.apriltag_workers);
+
         #[cfg(feature = "experimental")]
         if let Err(e) = audio_streaming::audio_streaming(
             self.lunabase_audio_streaming_address
@@ -118,7 +188,7 @@ impl LunabotApp {
 
         enumerate_cameras(
             &localizer_ref,
-            self.cameras.into_iter().map(
+            self.cameras.into_iter().filter_map(
                 |(
                     port,
                     CameraInfo {
@@ -126,56 +196,98 @@ impl LunabotApp {
                         focal_length_x_px,
                         focal_length_y_px,
                         stream_index,
+                        exposure,
+                        gain,
+                        min_apriltag_interval_ms,
+                        apriltag_params,
                     },
                 )| {
-                    (
+                    let node = match robot_layout.find_link(&link_name) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            error!("Failed to set up camera {port}: {e:#}");
+                            return None;
+                        }
+                    };
+                    Some((
                         port,
                         camera::CameraInfo {
-                            node: robot_chain
-                                .get_node_with_name(&link_name)
-                                .context("Failed to find camera link")
-                                .unwrap()
-                                .into(),
+                            node: node.into(),
                             focal_length_x_px,
                             focal_length_y_px,
                             stream_index,
+                            controls: camera::CameraControls { exposure, gain },
+                            min_apriltag_interval: Duration::from_millis(
+                                min_apriltag_interval_ms,
+                            ),
+                            apriltag_params,
                         },
-                    )
+                    ))
                 },
             ),
             apriltags,
+            &apriltag_pool,
         );
 
         let mut buffer = OwnedData::from(ThalassicData::default());
         let shared_thalassic_data = buffer.create_lendee();
 
+        let mut extrinsic_corrections =
+            load_extrinsic_corrections(self.extrinsic_calibration.as_deref());
+
         enumerate_depth_cameras(
             buffer,
             &localizer_ref,
-            self.depth_cameras.into_iter().map(
+            self.depth_cameras.into_iter().filter_map(
                 |(
                     serial,
                     DepthCameraInfo {
                         link_name,
                         ignore_apriltags: observe_apriltags,
                         stream_index,
+                        apriltag_params,
+                        min_depth,
+                        max_depth,
+                        decimation,
+                        temporal_filter,
+                        temporal_filter_frames,
+                        spatial_filter,
+                        fill_holes,
+                        max_in_flight_frames,
                     },
                 )| {
-                    (
+                    let node = match robot_layout.find_link(&link_name) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            error!("Failed to set up depth camera {serial}: {e:#}");
+                            return None;
+                        }
+                    };
+                    let extrinsic_correction = extrinsic_corrections
+                        .remove(&serial)
+                        .unwrap_or_else(Isometry3::identity);
+                    Some((
                         serial,
                         depth::DepthCameraInfo {
-                            node: robot_chain
-                                .get_node_with_name(&link_name)
-                                .context("Failed to find camera link")
-                                .unwrap()
-                                .into(),
+                            node: node.into(),
+                            extrinsic_correction,
                             ignore_apriltags: observe_apriltags,
                             stream_index,
+                            apriltag_params,
+                            min_depth,
+                            max_depth,
+                            decimation,
+                            temporal_filter,
+                            temporal_filter_frames,
+                            spatial_filter,
+                            fill_holes,
+                            max_in_flight_frames,
                         },
-                    )
+                    ))
                 },
             ),
             apriltags,
+            &apriltag_pool,
         );
 
         let grid_to_world = Transform3::from_matrix_unchecked(
@@ -224,22 +336,35 @@ impl LunabotApp {
         //     }
         // });
 
-        // let motor_ref = enumerate_motors();
+        let motor_ref = enumerate_motors();
+
+        // The part of the latest path that was planned through free cells
+        let mut planned_path = 0..0;
 
         run_ai(
             robot_chain.into(),
+            Duration::from_millis(self.min_steering_interval_ms),
             |action, inputs| match action {
                 Action::SetStage(stage) => {
                     lunabot_stage.store(stage);
                 }
                 Action::SetSteering(steering) => {
                     let (left, right) = steering.get_left_and_right();
-                    // motor_ref.set_speed(left as f32, right as f32);
+                    motor_ref.set_speed(left as f32, right as f32);
                 }
                 Action::CalculatePath { from, to, mut into } => {
-                    pathfinder.pathfind(&shared_thalassic_data, from, to, &mut into);
+                    planned_path = pathfinder.pathfind(&shared_thalassic_data, from, to, &mut into);
                     inputs.push(Input::PathCalculated(into));
                 }
+                Action::CheckPath { from, path } => {
+                    let path = path.get(planned_path.clone()).unwrap_or_default();
+                    if !pathfinder.is_path_clear(&shared_thalassic_data, from, path) {
+                        inputs.push(Input::PathBlocked);
+                    }
+                }
+                Action::SetObserveDepth(observe) => {
+                    set_observe_depth(observe);
+                }
             },
             |poll_when, inputs| {
                 let wait_disconnect = async {
@@ -299,6 +424,10 @@ impl LunabotApp {
                     }
                 }
             },
+            move || {
+                error!("Stopping the drive as the behavior tree is stuck");
+                motor_ref.set_speed(0.0, 0.0);
+            },
         );
     }
 }
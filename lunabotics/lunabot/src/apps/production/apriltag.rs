@@ -1,9 +1,18 @@
-use std::f64::consts::PI;
+use std::{
+    f64::consts::PI,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
-use apriltag::{families::TagStandard41h12, DetectorBuilder, Image, TagParams};
+use apriltag::{families::TagStandard41h12, Detector, DetectorBuilder, Image, TagParams};
 use apriltag_image::{image::ImageBuffer, ImageExt};
 use apriltag_nalgebra::PoseExt;
-use fxhash::FxHashMap;
+use crossbeam::{atomic::AtomicCell, channel::Sender};
+use fxhash::{FxHashMap, FxHashSet};
 use nalgebra::{Isometry3, Point3, UnitQuaternion, Vector3};
 use serde::Deserialize;
 
@@ -39,7 +48,7 @@ impl Apriltag {
 
 // Legacy code from urobotics-apriltag
 pub use apriltag_image::image;
-use tasker::{define_callbacks, fn_alias, shared::SharedDataReceiver};
+use tasker::{define_callbacks, fn_alias, parking_lot::Mutex, shared::SharedDataReceiver};
 use tracing::{error, warn};
 
 define_callbacks!(DetectionCallbacks => Fn(detection: TagObservation) + Send + Sync);
@@ -64,6 +73,8 @@ pub struct TagObservation {
     ///
     /// This is a value generated by the apriltag detector.
     pub decision_margin: f32,
+    /// When the image this tag was observed in was captured.
+    pub captured_at: Instant,
 }
 
 impl std::fmt::Debug for TagObservation {
@@ -71,6 +82,7 @@ impl std::fmt::Debug for TagObservation {
         f.debug_struct("PoseObservation")
             .field("pose", &self.tag_local_isometry)
             .field("decision_margin", &self.decision_margin)
+            .field("captured_at", &self.captured_at)
             .finish()
     }
 }
@@ -91,6 +103,56 @@ impl TagObservation {
     }
 }
 
+/// Parameters that trade detection range and accuracy for CPU time.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct DetectorParams {
+    /// Quads are detected on an image downscaled by this factor.
+    ///
+    /// Larger values are much faster but reduce the distance at which tags can be seen.
+    /// Decoding still uses the full resolution image, so accuracy is mostly unaffected.
+    pub decimation: f32,
+    /// The standard deviation in pixels of the gaussian blur applied before detecting quads.
+    ///
+    /// Blurring helps with noisy images, but small tags may be missed.
+    pub sigma: f32,
+    /// Whether quad edges should be snapped to strong gradients in the full resolution image.
+    ///
+    /// This recovers most of the accuracy lost by decimation at a small cost.
+    pub refine_edges: bool,
+}
+
+impl Default for DetectorParams {
+    fn default() -> Self {
+        Self {
+            decimation: 2.0,
+            sigma: 0.0,
+            refine_edges: true,
+        }
+    }
+}
+
+/// The settings of a detector that are controlled by [`DetectorParams`].
+trait DetectorSettings {
+    fn set_decimation(&mut self, decimation: f32);
+    fn set_sigma(&mut self, sigma: f32);
+    fn set_refine_edges(&mut self, refine_edges: bool);
+}
+
+impl DetectorSettings for Detector {
+    fn set_decimation(&mut self, decimation: f32) {
+        Detector::set_decimation(self, decimation);
+    }
+
+    fn set_sigma(&mut self, sigma: f32) {
+        Detector::set_sigma(self, sigma);
+    }
+
+    fn set_refine_edges(&mut self, refine_edges: bool) {
+        Detector::set_refine_edges(self, refine_edges);
+    }
+}
+
 struct KnownTag {
     pose: Isometry3<f64>,
     tag_params: TagParams,
@@ -99,11 +161,14 @@ struct KnownTag {
 /// A Node that can detect apriltags in images.
 ///
 /// Actual detection does not occur until the node
-/// is running.
+/// is added to an [`AprilTagPool`].
 pub struct AprilTagDetector {
     img_subscriber: SharedDataReceiver<ImageBuffer<image::Luma<u8>, Vec<u8>>>,
     detection_callbacks: DetectionCallbacks,
     known_tags: FxHashMap<usize, KnownTag>,
+    reported_unknown_tags: Mutex<FxHashSet<usize>>,
+    captured_at: AtomicCell<Instant>,
+    params: DetectorParams,
     pub focal_length_x_px: f64,
     pub focal_length_y_px: f64,
     pub image_width: u32,
@@ -126,11 +191,15 @@ impl AprilTagDetector {
         image_width: u32,
         image_height: u32,
         img_subscriber: SharedDataReceiver<ImageBuffer<image::Luma<u8>, Vec<u8>>>,
+        params: DetectorParams,
     ) -> Self {
         Self {
             img_subscriber,
             detection_callbacks: DetectionCallbacks::default(),
             known_tags: Default::default(),
+            reported_unknown_tags: Default::default(),
+            captured_at: AtomicCell::new(Instant::now()),
+            params,
             focal_length_x_px,
             focal_length_y_px,
             image_width,
@@ -143,6 +212,9 @@ impl AprilTagDetector {
     /// Orientations and positions should be in global space. If this
     /// is not known, any value can be used. However, [`TagObservation::get_isometry_of_observer`]
     /// will not produce correct results in that case.
+    ///
+    /// Each tag id has its own width, so tags of different sizes can be used together.
+    /// Detected tags that were never added are ignored, as their size is unknown.
     pub fn add_tag(
         &mut self,
         tag_position: Point3<f64>,
@@ -171,53 +243,307 @@ impl AprilTagDetector {
 }
 
 impl AprilTagDetector {
-    pub fn run(mut self) {
+    fn configure(&self, detector: &mut impl DetectorSettings) {
+        detector.set_decimation(self.params.decimation);
+        detector.set_sigma(self.params.sigma);
+        detector.set_refine_edges(self.params.refine_edges);
+    }
+
+    fn detect(&self, detector: &mut Detector) {
+        let Some(img) = self.img_subscriber.try_get() else {
+            return;
+        };
+        let captured_at = self.captured_at.load();
+        if img.width() != self.image_width || img.height() != self.image_height {
+            error!(
+                "Received incorrectly sized image: {}x{}",
+                img.width(),
+                img.height()
+            );
+            return;
+        }
+        let img = Image::from_image_buffer(&img);
+
+        // The detector is shared with other cameras, which may use different parameters
+        self.configure(detector);
+
+        for detection in detector.detect(&img) {
+            if detection.decision_margin() < 130.0 {
+                continue;
+            }
+            let Some(known) = self.known_tags.get(&detection.id()) else {
+                if self.reported_unknown_tags.lock().insert(detection.id()) {
+                    warn!(
+                        "Ignoring unregistered apriltag {} as its size is unknown",
+                        detection.id()
+                    );
+                }
+                continue;
+            };
+            let Some(tag_local_isometry) = detection.estimate_tag_pose(&known.tag_params) else {
+                warn!("Failed to estimate pose of {}", detection.id());
+                continue;
+            };
+            let mut tag_local_isometry = tag_local_isometry.to_na();
+            tag_local_isometry.translation.y *= -1.0;
+            tag_local_isometry.translation.z *= -1.0;
+            let mut scaled_axis = tag_local_isometry.rotation.scaled_axis();
+            scaled_axis.y *= -1.0;
+            scaled_axis.z *= -1.0;
+            tag_local_isometry.rotation = UnitQuaternion::from_scaled_axis(scaled_axis);
+            tag_local_isometry.rotation = UnitQuaternion::from_scaled_axis(
+                tag_local_isometry.rotation * Vector3::new(0.0, PI, 0.0),
+            ) * tag_local_isometry.rotation;
+
+            self.detection_callbacks.call_immut(TagObservation {
+                tag_local_isometry,
+                decision_margin: detection.decision_margin(),
+                tag_global_isometry: known.pose,
+                captured_at,
+            });
+        }
+    }
+}
+
+type DetectionJob = Box<dyn FnOnce(&mut Detector) + Send>;
+
+/// A fixed number of threads that run apriltag detection for any number of cameras.
+#[derive(Clone)]
+pub struct AprilTagPool {
+    job_tx: Sender<DetectionJob>,
+}
+
+impl AprilTagPool {
+    pub fn new(worker_count: NonZeroUsize) -> Self {
+        let (job_tx, job_rx) = crossbeam::channel::unbounded::<DetectionJob>();
+
+        for _ in 0..worker_count.get() {
+            let job_rx = job_rx.clone();
+            std::thread::spawn(move || {
+                let mut detector = DetectorBuilder::new()
+                    .add_family_bits(TagStandard41h12::default(), 1)
+                    .build()
+                    .unwrap();
+
+                while let Ok(job) = job_rx.recv() {
+                    job(&mut detector);
+                }
+            });
+        }
+
+        Self { job_tx }
+    }
+
+    fn submit(&self, job: impl FnOnce(&mut Detector) + Send + 'static) {
+        let _ = self.job_tx.send(Box::new(job));
+    }
+
+    /// Moves the given detector into this pool.
+    ///
+    /// Detection only occurs when [`AprilTagHandle::detect_shared`] is called.
+    pub fn add_detector(&self, detector: AprilTagDetector) -> AprilTagHandle {
+        AprilTagHandle {
+            detector: Arc::new(detector),
+            queued: Arc::new(AtomicBool::new(false)),
+            pool: self.clone(),
+        }
+    }
+}
+
+/// A handle to an [`AprilTagDetector`] that was moved into an [`AprilTagPool`].
+pub struct AprilTagHandle {
+    detector: Arc<AprilTagDetector>,
+    queued: Arc<AtomicBool>,
+    pool: AprilTagPool,
+}
+
+impl AprilTagHandle {
+    /// Queues detection on the image that was most recently shared with the detector.
+    ///
+    /// This should be called right after the image is shared, with the time the image was
+    /// captured. If detection is already queued for this detector, only the capture time
+    /// is updated.
+    pub fn detect_shared(&self, captured_at: Instant) {
+        self.detector.captured_at.store(captured_at);
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let detector = self.detector.clone();
+        let queued = self.queued.clone();
+        self.pool.submit(move |apriltag_detector| {
+            queued.store(false, Ordering::Release);
+            detector.detect(apriltag_detector);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::AtomicUsize, time::Duration};
+
+    use tasker::shared::OwnedData;
+
+    use super::*;
+
+    /// Draws tag `id` centered on `center`, with each cell of the tag `cell_px` pixels wide.
+    fn draw_tag(
+        image: &mut ImageBuffer<image::Luma<u8>, Vec<u8>>,
+        id: i32,
+        center: (u32, u32),
+        cell_px: u32,
+    ) {
+        unsafe {
+            let family = apriltag_sys::tagStandard41h12_create();
+            let tag = apriltag_sys::apriltag_to_image(family, id);
+            let tag_width = (*tag).width as u32 * cell_px;
+            for y in 0..tag_width {
+                for x in 0..tag_width {
+                    let cell = (y / cell_px) * (*tag).stride as u32 + x / cell_px;
+                    image.put_pixel(
+                        center.0 - tag_width / 2 + x,
+                        center.1 - tag_width / 2 + y,
+                        image::Luma([*(*tag).buf.add(cell as usize)]),
+                    );
+                }
+            }
+            apriltag_sys::image_u8_destroy(tag);
+            apriltag_sys::tagStandard41h12_destroy(family);
+        }
+    }
+
+    #[test]
+    fn tag_sizes_are_per_id() {
+        // Both tags are drawn with a black border that is 5 cells of 16 pixels wide
+        let mut image = ImageBuffer::from_pixel(640, 480, image::Luma([255]));
+        draw_tag(&mut image, 1, (170, 240), 16);
+        draw_tag(&mut image, 2, (470, 240), 16);
+        let mut image = OwnedData::from(image);
+        let mut det = AprilTagDetector::new(
+            500.0,
+            500.0,
+            640,
+            480,
+            image.create_lendee(),
+            DetectorParams::default(),
+        );
+        det.add_tag(Point3::origin(), UnitQuaternion::identity(), 0.1, 1);
+        det.add_tag(
+            Point3::new(1.0, 0.0, 0.0),
+            UnitQuaternion::identity(),
+            0.25,
+            2,
+        );
+
+        let small = &det.known_tags[&1];
+        let large = &det.known_tags[&2];
+        assert_eq!(small.tag_params.tagsize, 0.1);
+        assert_eq!(large.tag_params.tagsize, 0.25);
+        assert_eq!(large.pose.translation.x, 1.0);
+        assert_eq!(small.tag_params.fx, large.tag_params.fx);
+        assert!(!det.known_tags.contains_key(&3));
+
+        let (observation_tx, observation_rx) = crossbeam::channel::unbounded();
+        det.detection_callbacks_ref().add_fn(move |observation| {
+            let _ = observation_tx.send(observation);
+        });
+        let _image = image.pessimistic_share();
         let mut detector = DetectorBuilder::new()
             .add_family_bits(TagStandard41h12::default(), 1)
             .build()
             .unwrap();
+        det.detect(&mut detector);
 
-        loop {
-            let img = self.img_subscriber.get();
-            if img.width() != self.image_width || img.height() != self.image_height {
-                error!(
-                    "Received incorrectly sized image: {}x{}",
-                    img.width(),
-                    img.height()
-                );
-                continue;
+        let observations: Vec<TagObservation> = observation_rx.try_iter().collect();
+        assert_eq!(observations.len(), 2);
+        for observation in observations {
+            let tag_width = if observation.tag_global_isometry.translation.x == 0.0 {
+                0.1
+            } else {
+                0.25
+            };
+            // Both tags span 80 pixels, so the distance to each only depends on its own width
+            let expected_distance = tag_width * 500.0 / 80.0;
+            let distance = observation.tag_local_isometry.translation.z.abs();
+            assert!(
+                (distance - expected_distance).abs() < expected_distance * 0.02,
+                "{observation:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn detector_params_are_applied() {
+        #[derive(Default, Debug, PartialEq)]
+        struct Settings {
+            decimation: f32,
+            sigma: f32,
+            refine_edges: bool,
+        }
+
+        impl DetectorSettings for Settings {
+            fn set_decimation(&mut self, decimation: f32) {
+                self.decimation = decimation;
             }
-            let img = Image::from_image_buffer(&img);
 
-            for detection in detector.detect(&img) {
-                if detection.decision_margin() < 130.0 {
-                    continue;
-                }
-                let Some(known) = self.known_tags.get(&detection.id()) else {
-                    continue;
-                };
-                let Some(tag_local_isometry) = detection.estimate_tag_pose(&known.tag_params)
-                else {
-                    warn!("Failed to estimate pose of {}", detection.id());
-                    continue;
-                };
-                let mut tag_local_isometry = tag_local_isometry.to_na();
-                tag_local_isometry.translation.y *= -1.0;
-                tag_local_isometry.translation.z *= -1.0;
-                let mut scaled_axis = tag_local_isometry.rotation.scaled_axis();
-                scaled_axis.y *= -1.0;
-                scaled_axis.z *= -1.0;
-                tag_local_isometry.rotation = UnitQuaternion::from_scaled_axis(scaled_axis);
-                tag_local_isometry.rotation = UnitQuaternion::from_scaled_axis(
-                    tag_local_isometry.rotation * Vector3::new(0.0, PI, 0.0),
-                ) * tag_local_isometry.rotation;
-
-                self.detection_callbacks.call(TagObservation {
-                    tag_local_isometry,
-                    decision_margin: detection.decision_margin(),
-                    tag_global_isometry: known.pose,
+            fn set_sigma(&mut self, sigma: f32) {
+                self.sigma = sigma;
+            }
+
+            fn set_refine_edges(&mut self, refine_edges: bool) {
+                self.refine_edges = refine_edges;
+            }
+        }
+
+        let mut image = OwnedData::from(ImageBuffer::from_pixel(640, 480, image::Luma([0])));
+        let det = AprilTagDetector::new(
+            500.0,
+            510.0,
+            640,
+            480,
+            image.create_lendee(),
+            DetectorParams {
+                decimation: 4.0,
+                sigma: 0.8,
+                refine_edges: false,
+            },
+        );
+        let mut settings = Settings {
+            refine_edges: true,
+            ..Default::default()
+        };
+        det.configure(&mut settings);
+        assert_eq!(
+            settings,
+            Settings {
+                decimation: 4.0,
+                sigma: 0.8,
+                refine_edges: false,
+            }
+        );
+    }
+
+    #[test]
+    fn pool_runs_jobs_from_many_cameras() {
+        let pool = AprilTagPool::new(NonZeroUsize::new(2).unwrap());
+        let counts: Arc<[AtomicUsize]> = (0..5).map(|_| AtomicUsize::new(0)).collect();
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+
+        for camera in 0..counts.len() {
+            for _ in 0..3 {
+                let counts = counts.clone();
+                let done_tx = done_tx.clone();
+                pool.submit(move |_| {
+                    counts[camera].fetch_add(1, Ordering::Relaxed);
+                    let _ = done_tx.send(());
                 });
             }
         }
+
+        for _ in 0..15 {
+            done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        for count in counts.iter() {
+            assert_eq!(count.load(Ordering::Relaxed), 3);
+        }
     }
 }
@@ -0,0 +1,103 @@
+use anyhow::Context;
+use fxhash::FxHashMap;
+use nalgebra::{Isometry3, UnitQuaternion, Vector3};
+use serde::Deserialize;
+use tracing::error;
+
+/// A small correction to where a camera is mounted, relative to where the robot layout says
+/// it is mounted.
+///
+/// Both the translation and rotation are in the camera's local frame.
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(default)]
+pub struct ExtrinsicCorrection {
+    /// Meters along the camera's local x, y, and z axes.
+    pub translation: [f64; 3],
+    /// Roll, pitch, and yaw in radians.
+    pub rotation: [f64; 3],
+}
+
+impl ExtrinsicCorrection {
+    pub fn to_isometry(self) -> Isometry3<f64> {
+        let [roll, pitch, yaw] = self.rotation;
+        Isometry3::from_parts(
+            Vector3::from(self.translation).into(),
+            UnitQuaternion::from_euler_angles(roll, pitch, yaw),
+        )
+    }
+}
+
+/// Applies a correction in the camera's local frame on top of its kinematic transform.
+pub fn apply_correction(kinematic: Isometry3<f64>, correction: Isometry3<f64>) -> Isometry3<f64> {
+    kinematic * correction
+}
+
+fn parse_extrinsic_corrections(text: &str) -> anyhow::Result<FxHashMap<String, Isometry3<f64>>> {
+    let corrections: FxHashMap<String, ExtrinsicCorrection> = toml::from_str(text)?;
+    Ok(corrections
+        .into_iter()
+        .map(|(serial, correction)| (serial, correction.to_isometry()))
+        .collect())
+}
+
+/// Loads the extrinsic corrections of each camera by serial number from a TOML file.
+///
+/// Any errors are logged, and no corrections are returned.
+pub fn load_extrinsic_corrections(path: Option<&str>) -> FxHashMap<String, Isometry3<f64>> {
+    let Some(path) = path else {
+        return FxHashMap::default();
+    };
+    match std::fs::read_to_string(path)
+        .context("Failed to read file")
+        .and_then(|text| parse_extrinsic_corrections(&text))
+    {
+        Ok(corrections) => corrections,
+        Err(e) => {
+            error!("Failed to load extrinsic calibration from {path}: {e:#}");
+            FxHashMap::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use nalgebra::{Isometry3, Point3, Vector3};
+
+    use super::{apply_correction, parse_extrinsic_corrections};
+
+    #[test]
+    fn correction_composes_with_kinematic_transform() {
+        let corrections = parse_extrinsic_corrections(
+            r#"
+            ["123456"]
+            translation = [0.0, 0.0, -0.1]
+            rotation = [0.0, 0.0, 0.0]
+
+            ["654321"]
+            rotation = [0.0, 0.0, 1.5707963267948966]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(corrections.len(), 2);
+
+        // A camera 1 meter forward (-Z) of the origin, rotated 90 degrees about Y
+        let kinematic = Isometry3::new(
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, FRAC_PI_2, 0.0),
+        );
+
+        // Moving the camera back along its own Z moves it along the robot's X
+        let corrected = apply_correction(kinematic, corrections["123456"]);
+        let origin = corrected * Point3::origin();
+        assert!((origin - Point3::new(-0.1, 0.0, -1.0)).magnitude() < 1e-9);
+
+        // A rotation only correction does not move the camera
+        let corrected = apply_correction(kinematic, corrections["654321"]);
+        let origin = corrected * Point3::origin();
+        assert!((origin - Point3::new(0.0, 0.0, -1.0)).magnitude() < 1e-9);
+        let right = corrected * Vector3::x();
+        assert!((right - Vector3::y()).magnitude() < 1e-9);
+    }
+}
@@ -1,25 +1,36 @@
 use std::{
     cell::OnceCell,
+    ffi::OsStr,
+    fmt::Display,
     io::Cursor,
     path::PathBuf,
-    sync::mpsc::{Receiver, SyncSender},
+    sync::mpsc::{Receiver, RecvError, Sender},
+    time::{Duration, Instant},
 };
 
 use super::apriltag::{
     image::{self, ImageBuffer, ImageDecoder, Luma},
-    AprilTagDetector,
+    AprilTagDetector, AprilTagHandle, AprilTagPool, DetectorParams,
 };
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 use simple_motion::StaticImmutableNode;
 use tasker::shared::{MaybeOwned, OwnedData};
 use tracing::{error, info, warn};
 use udev::{EventType, MonitorBuilder, Udev};
-use v4l::{buffer::Type, io::traits::CaptureStream, prelude::MmapStream, video::Capture};
+use v4l::{
+    buffer::{Flags, Metadata, Type},
+    control::{Control, Value},
+    io::traits::CaptureStream,
+    prelude::MmapStream,
+    video::Capture,
+    FourCC,
+};
 
 use crate::localization::LocalizerRef;
 
 use super::{
     apriltag::Apriltag,
+    depth::list_realsense_cameras,
     streaming::{CameraStream, DownscaleRgbImageReader},
 };
 
@@ -28,14 +39,59 @@ pub struct CameraInfo {
     pub focal_length_x_px: f64,
     pub focal_length_y_px: f64,
     pub stream_index: usize,
+    pub controls: CameraControls,
+    /// The minimum time between frames that are given to the apriltag detector.
+    ///
+    /// Frames arriving sooner are still streamed.
+    pub min_apriltag_interval: Duration,
+    pub apriltag_params: DetectorParams,
+}
+
+const V4L2_CID_EXPOSURE_AUTO: u32 = 0x009a0901;
+const V4L2_CID_EXPOSURE_ABSOLUTE: u32 = 0x009a0902;
+const V4L2_CID_GAIN: u32 = 0x00980913;
+const V4L2_EXPOSURE_MANUAL: i64 = 1;
+
+/// V4L controls that are applied to a camera before it starts streaming.
+///
+/// Controls that are `None` are left at whatever the camera defaults to.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CameraControls {
+    /// Exposure time in units of 100µs. Setting this turns off auto exposure.
+    pub exposure: Option<i64>,
+    pub gain: Option<i64>,
+}
+
+impl CameraControls {
+    fn to_v4l_controls(self) -> Vec<Control> {
+        let mut controls = vec![];
+        if let Some(exposure) = self.exposure {
+            controls.push(Control {
+                id: V4L2_CID_EXPOSURE_AUTO,
+                value: Value::Integer(V4L2_EXPOSURE_MANUAL),
+            });
+            controls.push(Control {
+                id: V4L2_CID_EXPOSURE_ABSOLUTE,
+                value: Value::Integer(exposure),
+            });
+        }
+        if let Some(gain) = self.gain {
+            controls.push(Control {
+                id: V4L2_CID_GAIN,
+                value: Value::Integer(gain),
+            });
+        }
+        controls
+    }
 }
 
 pub fn enumerate_cameras(
     localizer_ref: &LocalizerRef,
     port_to_chain: impl IntoIterator<Item = (String, CameraInfo)>,
     apriltags: &'static [(usize, Apriltag)],
+    apriltag_pool: &AprilTagPool,
 ) {
-    let mut threads: FxHashMap<String, SyncSender<PathBuf>> = port_to_chain
+    let mut threads: FxHashMap<String, Sender<PathBuf>> = port_to_chain
         .into_iter()
         .filter_map(
             |(
@@ -45,6 +101,9 @@ pub fn enumerate_cameras(
                     focal_length_x_px,
                     focal_length_y_px,
                     stream_index,
+                    controls,
+                    min_apriltag_interval,
+                    apriltag_params,
                 },
             )| {
                 let Some(camera_stream) = CameraStream::new(stream_index) else {
@@ -52,18 +111,24 @@ pub fn enumerate_cameras(
                 };
                 let port2 = port.clone();
                 let localizer_ref = localizer_ref.clone();
-                let (tx, rx) = std::sync::mpsc::sync_channel(1);
+                let apriltag_pool = apriltag_pool.clone();
+                let (tx, rx) = std::sync::mpsc::channel();
                 std::thread::spawn(move || {
                     let mut camera_task = CameraTask {
                         path: rx,
                         port,
                         camera_stream,
                         image: OnceCell::new(),
+                        apriltag: OnceCell::new(),
+                        apriltag_pool,
                         focal_length_x_px,
                         focal_length_y_px,
                         apriltags,
                         localizer_ref,
                         node,
+                        controls,
+                        min_apriltag_interval,
+                        apriltag_params,
                     };
                     loop {
                         camera_task.camera_task();
@@ -120,43 +185,30 @@ pub fn enumerate_cameras(
                 return;
             }
         };
-        devices
+        let connected: Vec<_> = devices
+            .filter_map(|device| describe_udev_device(&device))
+            .collect();
+        report_conflicts(&connected, "port", |camera| camera.port.as_deref());
+        connected
             .into_iter()
             .chain(
                 listener
                     .iter()
                     .filter(|event| event.event_type() == EventType::Add)
-                    .map(|event| event.device()),
+                    .filter_map(|event| describe_udev_device(&event.device())),
             )
-            .for_each(|device| {
-                let Some(path) = device.devnode() else {
+            .for_each(|camera| {
+                let Some(path) = camera.path else {
                     return;
                 };
-                // Valid camera paths are of the form /dev/videoN
-                let Some(path_str) = path.to_str() else {
+                let Some(port) = camera.port else {
+                    warn!("No port for camera {}", path.display());
                     return;
                 };
-                if !path_str.starts_with("/dev/video") {
-                    return;
-                }
-                if let Some(name) = device.attribute_value("name") {
-                    if let Some(name) = name.to_str() {
-                        if name.contains("RealSense") {
-                            return;
-                        }
-                    }
-                }
-                let Some(port_raw) = device.property_value("ID_PATH") else {
-                    warn!("No port for camera {path_str}");
-                    return;
-                };
-                let Some(port) = port_raw.to_str() else {
-                    warn!("Failed to parse port of camera {path_str}");
-                    return;
-                };
-                if let Some(path_sender) = threads.get(port) {
-                    if path_sender.send(path.to_path_buf()).is_err() {
-                        threads.remove(port);
+                if let Some(path_sender) = threads.get(&port) {
+                    info!("Found camera {} on port {port}", path.display());
+                    if path_sender.send(path).is_err() {
+                        threads.remove(&port);
                     }
                 } else {
                     warn!("Unexpected camera with port {}", port);
@@ -165,21 +217,261 @@ pub fn enumerate_cameras(
     });
 }
 
+/// The kind of a connected camera.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraKind {
+    V4l,
+    RealSense,
+}
+
+/// A connected camera, as found without opening it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CameraDescriptor {
+    pub kind: CameraKind,
+    /// The device node of V4L cameras, such as `/dev/video0`.
+    pub path: Option<PathBuf>,
+    /// The port that V4L cameras are configured by. RealSense cameras report their
+    /// physical port, which is not stable across reconnects.
+    pub port: Option<String>,
+    /// The serial number that RealSense cameras are configured by.
+    pub serial: Option<String>,
+    pub name: Option<String>,
+    /// The V4L capabilities of V4L cameras, such as `capture`. For RealSense cameras, this is
+    /// the USB type, such as `usb3.2`, as depth is only streamed over USB 3.
+    pub capabilities: Vec<String>,
+}
+
+impl Display for CameraDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name.as_deref().unwrap_or("Unnamed camera"))?;
+        if let Some(path) = &self.path {
+            write!(f, " at {}", path.display())?;
+        } else if let Some(port) = &self.port {
+            write!(f, " on port {port}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Logs an error for every group of cameras that share the same `key`, such as the same port
+/// or serial number. Such cameras cannot be told apart, so only one of them will be used.
+///
+/// Returns the keys that are shared.
+pub fn report_conflicts<'a>(
+    cameras: &'a [CameraDescriptor],
+    key_name: &str,
+    key: impl Fn(&'a CameraDescriptor) -> Option<&'a str>,
+) -> FxHashSet<&'a str> {
+    let mut by_key: FxHashMap<&str, Vec<&CameraDescriptor>> = FxHashMap::default();
+    for camera in cameras {
+        if let Some(key) = key(camera) {
+            by_key.entry(key).or_default().push(camera);
+        }
+    }
+    by_key
+        .into_iter()
+        .filter(|(_, cameras)| cameras.len() > 1)
+        .map(|(key, cameras)| {
+            let names = cameras
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            error!(
+                "{} cameras share the {key_name} {key}, so only one of them will be used: {names}",
+                cameras.len()
+            );
+            key
+        })
+        .collect()
+}
+
+/// Describes the V4L device at `path` from its udev attributes and properties, or returns
+/// `None` if it is not a camera that can be streamed from.
+///
+/// RealSense cameras are skipped, as they are streamed through the RealSense SDK instead.
+fn describe_v4l_device<'a>(
+    path: &str,
+    attribute: impl Fn(&str) -> Option<&'a str>,
+    property: impl Fn(&str) -> Option<&'a str>,
+) -> Option<CameraDescriptor> {
+    // Valid camera paths are of the form /dev/videoN
+    if !path.starts_with("/dev/video") {
+        return None;
+    }
+    let name = attribute("name");
+    if name.is_some_and(|name| name.contains("RealSense")) {
+        return None;
+    }
+    let capabilities = property("ID_V4L_CAPABILITIES");
+    // Cameras also expose metadata nodes, which cannot be streamed from
+    if capabilities.is_some_and(|capabilities| !is_capture_device(capabilities)) {
+        return None;
+    }
+    Some(CameraDescriptor {
+        kind: CameraKind::V4l,
+        path: Some(PathBuf::from(path)),
+        port: property("ID_PATH").map(String::from),
+        serial: property("ID_SERIAL_SHORT").map(String::from),
+        name: name.map(String::from),
+        capabilities: capabilities
+            .into_iter()
+            .flat_map(|capabilities| capabilities.split(':'))
+            .filter(|capability| !capability.is_empty())
+            .map(String::from)
+            .collect(),
+    })
+}
+
+fn describe_udev_device(device: &udev::Device) -> Option<CameraDescriptor> {
+    describe_v4l_device(
+        device.devnode()?.to_str()?,
+        |key| device.attribute_value(key).and_then(OsStr::to_str),
+        |key| device.property_value(key).and_then(OsStr::to_str),
+    )
+}
+
+fn list_v4l_cameras() -> Vec<CameraDescriptor> {
+    let mut enumerator = match udev::Enumerator::new() {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to create udev enumerator: {e}");
+            return vec![];
+        }
+    };
+    if let Err(e) = enumerator.match_subsystem("video4linux") {
+        error!("Failed to set match-subsystem filter: {e}");
+        return vec![];
+    }
+    let devices = match enumerator.scan_devices() {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to scan devices: {e}");
+            return vec![];
+        }
+    };
+    devices
+        .filter_map(|device| describe_udev_device(&device))
+        .collect()
+}
+
+/// Lists every connected camera without opening any of them, so that it can be checked that
+/// every expected camera is present without disturbing cameras that are streaming.
+#[allow(dead_code)]
+pub fn list_cameras() -> Vec<CameraDescriptor> {
+    let mut cameras = list_v4l_cameras();
+    cameras.extend(list_realsense_cameras());
+    cameras
+}
+
+/// The pixel formats that camera frames can be decoded from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FrameFormat {
+    Mjpeg,
+    Yuyv,
+}
+
+impl FrameFormat {
+    fn from_fourcc(fourcc: FourCC) -> Option<Self> {
+        match &fourcc.repr {
+            b"MJPG" => Some(Self::Mjpeg),
+            b"YUYV" => Some(Self::Yuyv),
+            _ => None,
+        }
+    }
+}
+
+/// Converts packed YUYV (YUV 4:2:2) into RGB using the BT.601 coefficients.
+///
+/// `rgb` must have room for 3 bytes per pixel, where every 4 bytes of `yuyv` encode 2 pixels.
+fn yuyv_to_rgb(yuyv: &[u8], rgb: &mut [u8]) {
+    fn to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+        let c = (y as i32 - 16) * 298;
+        let d = u as i32 - 128;
+        let e = v as i32 - 128;
+        [
+            ((c + 409 * e + 128) >> 8).clamp(0, 255) as u8,
+            ((c - 100 * d - 208 * e + 128) >> 8).clamp(0, 255) as u8,
+            ((c + 516 * d + 128) >> 8).clamp(0, 255) as u8,
+        ]
+    }
+
+    yuyv.array_chunks::<4>()
+        .zip(rgb.array_chunks_mut::<6>())
+        .for_each(|(&[y0, u, y1, v], dst)| {
+            dst[0..3].copy_from_slice(&to_rgb(y0, u, v));
+            dst[3..6].copy_from_slice(&to_rgb(y1, u, v));
+        });
+}
+
 struct CameraTask {
     path: Receiver<PathBuf>,
     port: String,
     camera_stream: CameraStream,
     image: OnceCell<MaybeOwned<ImageBuffer<Luma<u8>, Vec<u8>>>>,
+    apriltag: OnceCell<AprilTagHandle>,
+    apriltag_pool: AprilTagPool,
     focal_length_x_px: f64,
     focal_length_y_px: f64,
     apriltags: &'static [(usize, Apriltag)],
     localizer_ref: LocalizerRef,
     node: StaticImmutableNode,
+    controls: CameraControls,
+    min_apriltag_interval: Duration,
+    apriltag_params: DetectorParams,
+}
+
+/// Returns `true` if the given `ID_V4L_CAPABILITIES` udev property describes a device that
+/// can capture video.
+fn is_capture_device(capabilities: &str) -> bool {
+    capabilities
+        .split(':')
+        .any(|capability| capability == "capture")
+}
+
+/// Blocks until a path is received, then returns the most recent path in the channel.
+///
+/// Paths are only sent when a camera is (re)connected, so any older path refers to a device
+/// node that no longer exists.
+fn recv_latest_path(path: &Receiver<PathBuf>) -> Result<PathBuf, RecvError> {
+    let mut latest = path.recv()?;
+    while let Ok(newer) = path.try_recv() {
+        latest = newer;
+    }
+    Ok(latest)
+}
+
+/// Returns `true` if enough time has passed since the last frame given to the apriltag detector.
+fn apriltag_frame_due(last: Option<Instant>, now: Instant, min_interval: Duration) -> bool {
+    last.map_or(true, |last| now.duration_since(last) >= min_interval)
+}
+
+/// Finds when a frame was captured from the timestamp the driver gave its buffer.
+///
+/// `Instant` uses the monotonic clock, so monotonic buffer timestamps are related to `now`
+/// through the current monotonic time. Buffers timestamped any other way are assumed to have
+/// been captured `now`.
+fn buffer_captured_at(metadata: &Metadata, now: Instant) -> Instant {
+    if metadata.flags & Flags::TIMESTAMP_MASK != Flags::TIMESTAMP_MONOTONIC {
+        return now;
+    }
+    let mut monotonic_now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut monotonic_now) } != 0 {
+        return now;
+    }
+    let monotonic_now = Duration::new(monotonic_now.tv_sec as u64, monotonic_now.tv_nsec as u32);
+    let captured = Duration::from_secs(metadata.timestamp.sec as u64)
+        + Duration::from_micros(metadata.timestamp.usec as u64);
+    now.checked_sub(monotonic_now.saturating_sub(captured))
+        .unwrap_or(now)
 }
 
 impl CameraTask {
     fn camera_task(&mut self) {
-        let path = match self.path.recv() {
+        let path = match recv_latest_path(&self.path) {
             Ok(x) => x,
             Err(_) => loop {
                 std::thread::park();
@@ -199,6 +491,13 @@ impl CameraTask {
                 return;
             }
         };
+        let Some(frame_format) = FrameFormat::from_fourcc(format.fourcc) else {
+            error!(
+                "Camera {} uses unsupported pixel format {}",
+                self.port, format.fourcc
+            );
+            return;
+        };
         let image = if let Some(image) = self.image.get_mut() {
             if image.width() != format.width || image.height() != format.height {
                 warn!("Camera {} format changed", self.port);
@@ -217,6 +516,7 @@ impl CameraTask {
                 format.width,
                 format.height,
                 image.create_lendee(),
+                self.apriltag_params,
             );
             for (tag_id, tag) in self.apriltags {
                 det.add_tag(tag.tag_position, tag.get_quat(), tag.tag_width, *tag_id);
@@ -246,15 +546,27 @@ impl CameraTask {
                 //     pose.rotation.axis().unwrap().y,
                 //     pose.rotation.axis().unwrap().z,
                 // );
-                localizer_ref
-                    .set_april_tag_isometry(inverse_local * observation.get_isometry_of_observer());
+                localizer_ref.set_april_tag_isometry(
+                    inverse_local * observation.get_isometry_of_observer(),
+                    observation.captured_at,
+                );
             });
-            std::thread::spawn(move || det.run());
+            let _ = self.apriltag.set(self.apriltag_pool.add_detector(det));
             let _ = self.image.set(image.into());
             self.image.get_mut().unwrap()
         };
         info!("Camera {} opened", self.port);
 
+        for control in self.controls.to_v4l_controls() {
+            let id = control.id;
+            if let Err(e) = camera.set_control(control) {
+                warn!(
+                    "Failed to set control {id:#x} for camera {}: {e}",
+                    self.port
+                );
+            }
+        }
+
         let mut stream = match MmapStream::with_buffers(&mut camera, Type::VideoCapture, 4) {
             Ok(x) => x,
             Err(e) => {
@@ -264,25 +576,45 @@ impl CameraTask {
         };
 
         let mut rgb_img = vec![0u8; format.width as usize * format.height as usize * 3];
+        let mut last_apriltag_frame = None;
         loop {
-            let (jpg_img, _) = match stream.next() {
+            let (frame, metadata) = match stream.next() {
                 Ok(x) => x,
                 Err(e) => {
-                    warn!("Failed to get next frame from camera {}: {e}", self.port);
+                    warn!(
+                        "Failed to get next frame from camera {}, waiting for it to reconnect: {e}",
+                        self.port
+                    );
                     break;
                 }
             };
+            let captured_at = buffer_captured_at(metadata, Instant::now());
 
-            match image::codecs::jpeg::JpegDecoder::new(Cursor::new(jpg_img)) {
-                Ok(decoder) => {
-                    if let Err(e) = decoder.read_image(&mut rgb_img) {
-                        error!("Failed to decode JPEG image: {e}");
-                        continue;
+            match frame_format {
+                FrameFormat::Mjpeg => {
+                    match image::codecs::jpeg::JpegDecoder::new(Cursor::new(frame)) {
+                        Ok(decoder) => {
+                            if let Err(e) = decoder.read_image(&mut rgb_img) {
+                                error!("Failed to decode JPEG image: {e}");
+                                continue;
+                            }
+                        }
+                        Err(e) => {
+                            error!("Failed to create JPEG decoder: {e}");
+                            continue;
+                        }
                     }
                 }
-                Err(e) => {
-                    error!("Failed to create JPEG decoder: {e}");
-                    continue;
+                FrameFormat::Yuyv => {
+                    if frame.len() < rgb_img.len() / 3 * 2 {
+                        error!(
+                            "Incomplete YUYV frame from camera {}: {} bytes",
+                            self.port,
+                            frame.len()
+                        );
+                        continue;
+                    }
+                    yuyv_to_rgb(frame, &mut rgb_img);
                 }
             }
 
@@ -294,7 +626,11 @@ impl CameraTask {
                 ))
                 .unwrap();
 
+            if !apriltag_frame_due(last_apriltag_frame, captured_at, self.min_apriltag_interval) {
+                continue;
+            }
             if image.try_recall() {
+                last_apriltag_frame = Some(captured_at);
                 let owned_image: &mut ImageBuffer<Luma<u8>, Vec<u8>> = image.get_mut().unwrap();
                 owned_image
                     .iter_mut()
@@ -305,8 +641,223 @@ impl CameraTask {
                         *dst = new;
                     });
                 image.share();
+                if let Some(apriltag) = self.apriltag.get() {
+                    apriltag.detect_shared(captured_at);
+                }
             }
         }
-        error!("Camera {} task exited", self.port);
+        warn!("Camera {} closed", self.port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_yuyv() {
+        let yuyv = [16, 128, 235, 128, 81, 90, 81, 240];
+        let mut rgb = [0u8; 12];
+        yuyv_to_rgb(&yuyv, &mut rgb);
+        assert_eq!(rgb, [0, 0, 0, 255, 255, 255, 255, 0, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn frame_format_from_fourcc() {
+        assert_eq!(
+            FrameFormat::from_fourcc(FourCC::new(b"MJPG")),
+            Some(FrameFormat::Mjpeg)
+        );
+        assert_eq!(
+            FrameFormat::from_fourcc(FourCC::new(b"YUYV")),
+            Some(FrameFormat::Yuyv)
+        );
+        assert_eq!(FrameFormat::from_fourcc(FourCC::new(b"GREY")), None);
+    }
+
+    #[test]
+    fn apriltag_frame_interval() {
+        let start = Instant::now();
+        let interval = Duration::from_millis(200);
+        assert!(apriltag_frame_due(None, start, interval));
+        assert!(!apriltag_frame_due(
+            Some(start),
+            start + Duration::from_millis(33),
+            interval
+        ));
+        assert!(!apriltag_frame_due(
+            Some(start),
+            start + Duration::from_millis(199),
+            interval
+        ));
+        assert!(apriltag_frame_due(Some(start), start + interval, interval));
+        assert!(apriltag_frame_due(Some(start), start, Duration::ZERO));
+    }
+
+    #[test]
+    fn captured_at_from_buffer_timestamp() {
+        let mut monotonic_now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        assert_eq!(
+            unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut monotonic_now) },
+            0
+        );
+        let now = Instant::now();
+        let age = Duration::from_millis(50);
+        let captured =
+            Duration::new(monotonic_now.tv_sec as u64, monotonic_now.tv_nsec as u32) - age;
+        let mut metadata = Metadata {
+            flags: Flags::TIMESTAMP_MONOTONIC,
+            timestamp: captured.into(),
+            ..Default::default()
+        };
+        let captured_at = buffer_captured_at(&metadata, now);
+        // The monotonic clock keeps running between reading it here and in the conversion
+        let error = (now - captured_at).abs_diff(age);
+        assert!(error < Duration::from_millis(20), "{error:?}");
+
+        metadata.flags = Flags::TIMESTAMP_COPY;
+        assert_eq!(buffer_captured_at(&metadata, now), now);
+    }
+
+    #[test]
+    fn describe_v4l_devices() {
+        let attributes: FxHashMap<_, _> = [
+            (("/dev/video0", "name"), "HD USB Camera"),
+            (("/dev/video1", "name"), "HD USB Camera"),
+            (
+                ("/dev/video2", "name"),
+                "Intel(R) RealSense(TM) Depth Camera 435 RGB",
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let properties: FxHashMap<_, _> = [
+            (("/dev/video0", "ID_V4L_CAPABILITIES"), ":capture:"),
+            (("/dev/video0", "ID_PATH"), "pci-0000:00:14.0-usb-0:1:1.0"),
+            (("/dev/video0", "ID_SERIAL_SHORT"), "SN0001"),
+            (("/dev/video1", "ID_V4L_CAPABILITIES"), ":"),
+            (("/dev/video1", "ID_PATH"), "pci-0000:00:14.0-usb-0:1:1.0"),
+            (("/dev/video2", "ID_V4L_CAPABILITIES"), ":capture:"),
+        ]
+        .into_iter()
+        .collect();
+        let describe = |path| {
+            describe_v4l_device(
+                path,
+                |key| attributes.get(&(path, key)).copied(),
+                |key| properties.get(&(path, key)).copied(),
+            )
+        };
+
+        assert_eq!(
+            describe("/dev/video0"),
+            Some(CameraDescriptor {
+                kind: CameraKind::V4l,
+                path: Some(PathBuf::from("/dev/video0")),
+                port: Some("pci-0000:00:14.0-usb-0:1:1.0".into()),
+                serial: Some("SN0001".into()),
+                name: Some("HD USB Camera".into()),
+                capabilities: vec!["capture".into()],
+            })
+        );
+        // Metadata node of the same camera
+        assert_eq!(describe("/dev/video1"), None);
+        // Streamed through the RealSense SDK
+        assert_eq!(describe("/dev/video2"), None);
+        assert_eq!(describe("/dev/media0"), None);
+
+        // Devices without a port are still listed
+        let camera = describe_v4l_device("/dev/video3", |_| None, |_| None).unwrap();
+        assert_eq!(camera.port, None);
+        assert!(camera.capabilities.is_empty());
+    }
+
+    #[test]
+    fn conflicting_cameras_are_reported() {
+        let v4l = |path: &str, port: &str| CameraDescriptor {
+            kind: CameraKind::V4l,
+            path: Some(PathBuf::from(path)),
+            port: Some(port.into()),
+            serial: None,
+            name: Some("HD USB Camera".into()),
+            capabilities: vec!["capture".into()],
+        };
+        let cameras = [
+            v4l("/dev/video0", "usb-0:1:1.0"),
+            v4l("/dev/video2", "usb-0:2:1.0"),
+            v4l("/dev/video4", "usb-0:1:1.0"),
+        ];
+        let conflicts = report_conflicts(&cameras, "port", |camera| camera.port.as_deref());
+        assert_eq!(conflicts, ["usb-0:1:1.0"].into_iter().collect());
+        assert_eq!(cameras[2].to_string(), "HD USB Camera at /dev/video4");
+
+        let realsense = |serial: &str, port: &str| CameraDescriptor {
+            kind: CameraKind::RealSense,
+            path: None,
+            port: Some(port.into()),
+            serial: Some(serial.into()),
+            name: None,
+            capabilities: vec![],
+        };
+        let cameras = [realsense("123456", "2-1"), realsense("654321", "2-2")];
+        assert!(report_conflicts(&cameras, "serial", |camera| camera.serial.as_deref()).is_empty());
+        let cameras = [realsense("123456", "2-1"), realsense("123456", "2-2")];
+        assert_eq!(
+            report_conflicts(&cameras, "serial", |camera| camera.serial.as_deref()).len(),
+            1
+        );
+        assert_eq!(cameras[1].to_string(), "Unnamed camera on port 2-2");
+    }
+
+    #[test]
+    fn capture_device_capabilities() {
+        assert!(is_capture_device(":capture:"));
+        assert!(is_capture_device(":capture:video_output:"));
+        assert!(!is_capture_device(":"));
+        assert!(!is_capture_device(":video_output:"));
+    }
+
+    #[test]
+    fn reconnect_uses_latest_path() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        tx.send(PathBuf::from("/dev/video0")).unwrap();
+        assert_eq!(recv_latest_path(&rx).unwrap(), PathBuf::from("/dev/video0"));
+
+        // Unplugged and replugged twice while the task was busy
+        tx.send(PathBuf::from("/dev/video2")).unwrap();
+        tx.send(PathBuf::from("/dev/video4")).unwrap();
+        assert_eq!(recv_latest_path(&rx).unwrap(), PathBuf::from("/dev/video4"));
+
+        drop(tx);
+        assert!(recv_latest_path(&rx).is_err());
+    }
+
+    #[test]
+    fn camera_controls() {
+        assert!(CameraControls::default().to_v4l_controls().is_empty());
+
+        let controls = CameraControls {
+            exposure: Some(150),
+            gain: Some(32),
+        }
+        .to_v4l_controls();
+        let controls: Vec<_> = controls
+            .into_iter()
+            .map(|control| match control.value {
+                Value::Integer(value) => (control.id, value),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            controls,
+            [
+                (V4L2_CID_EXPOSURE_AUTO, V4L2_EXPOSURE_MANUAL),
+                (V4L2_CID_EXPOSURE_ABSOLUTE, 150),
+                (V4L2_CID_GAIN, 32),
+            ]
+        );
     }
 }
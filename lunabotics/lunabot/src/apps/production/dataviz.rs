@@ -1,12 +1,14 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, num::NonZeroUsize, sync::Arc};
 
-use super::{depth::enumerate_depth_cameras, subaddress_of};
-use anyhow::Context;
+use super::{
+    apriltag::AprilTagPool, calibration::load_extrinsic_corrections,
+    depth::enumerate_depth_cameras, subaddress_of,
+};
 use common::LunabotStage;
 use crossbeam::atomic::AtomicCell;
 use fxhash::FxHashMap;
 use gputter::init_gputter_blocking;
-use simple_motion::{ChainBuilder, NodeSerde};
+use nalgebra::Isometry3;
 use tasker::shared::OwnedData;
 use tracing::error;
 
@@ -14,6 +16,7 @@ use crate::{
     apps::log_teleop_messages,
     localization::Localizer,
     pipelines::thalassic::{set_observe_depth, ThalassicData},
+    robot_layout::{ExpectedJoint, ExpectedLayout, RobotLayout},
 };
 
 use super::{create_packet_builder, DepthCameraInfo};
@@ -24,6 +27,7 @@ pub struct DatavizApp {
     pub max_pong_delay_ms: u64,
     pub depth_cameras: FxHashMap<String, DepthCameraInfo>,
     pub robot_layout: String,
+    pub extrinsic_calibration: Option<String>,
 }
 
 impl DatavizApp {
@@ -33,11 +37,27 @@ impl DatavizApp {
             error!("Failed to initialize gputter: {e}");
         }
 
-        let robot_chain = NodeSerde::from_reader(
-            std::fs::File::open(self.robot_layout).expect("Failed to read robot chain"),
-        )
-        .expect("Failed to parse robot chain");
-        let robot_chain = ChainBuilder::from(robot_chain).finish_static();
+        let robot_layout = match RobotLayout::load(self.robot_layout) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("{e:#}");
+                return;
+            }
+        };
+        let expected_layout = ExpectedLayout {
+            // The localizer sets the isometry of the root
+            root: ExpectedJoint::FREE,
+            links: self
+                .depth_cameras
+                .values()
+                .map(|camera| (camera.link_name.as_str(), ExpectedJoint::ANY))
+                .collect(),
+        };
+        if let Err(e) = robot_layout.validate(&expected_layout) {
+            error!("{e:#}");
+            return;
+        }
+        let robot_chain = robot_layout.chain;
 
         let localizer = Localizer::new(robot_chain.clone(), None);
         let localizer_ref = localizer.get_ref();
@@ -45,33 +65,62 @@ impl DatavizApp {
         let mut buffer = OwnedData::from(ThalassicData::default());
         let shared_thalassic_data = buffer.create_lendee();
 
+        let mut extrinsic_corrections =
+            load_extrinsic_corrections(self.extrinsic_calibration.as_deref());
+
         enumerate_depth_cameras(
             buffer,
             &localizer_ref,
-            self.depth_cameras.into_iter().map(
+            self.depth_cameras.into_iter().filter_map(
                 |(
                     serial,
                     DepthCameraInfo {
                         link_name,
                         ignore_apriltags: observe_apriltags,
                         stream_index,
+                        apriltag_params,
+                        min_depth,
+                        max_depth,
+                        decimation,
+                        temporal_filter,
+                        temporal_filter_frames,
+                        spatial_filter,
+                        fill_holes,
+                        max_in_flight_frames,
                     },
                 )| {
-                    (
+                    let node = match robot_layout.find_link(&link_name) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            error!("Failed to set up depth camera {serial}: {e:#}");
+                            return None;
+                        }
+                    };
+                    let extrinsic_correction = extrinsic_corrections
+                        .remove(&serial)
+                        .unwrap_or_else(Isometry3::identity);
+                    Some((
                         serial,
                         super::depth::DepthCameraInfo {
-                            node: robot_chain
-                                .get_node_with_name(&link_name)
-                                .context("Failed to find camera link")
-                                .unwrap()
-                                .into(),
+                            node: node.into(),
+                            extrinsic_correction,
                             ignore_apriltags: observe_apriltags,
                             stream_index,
+                            apriltag_params,
+                            min_depth,
+                            max_depth,
+                            decimation,
+                            temporal_filter,
+                            temporal_filter_frames,
+                            spatial_filter,
+                            fill_holes,
+                            max_in_flight_frames,
                         },
-                    )
+                    ))
                 },
             ),
             &[],
+            &AprilTagPool::new(NonZeroUsize::MIN),
         );
         let data_address = self
             .lunabase_data_address
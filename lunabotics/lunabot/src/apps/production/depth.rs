@@ -1,21 +1,21 @@
 use std::{
-    cell::OnceCell, num::NonZeroU32, sync::{mpsc::{Receiver, Sender, SyncSender}, Arc}
+    cell::OnceCell, collections::HashSet, num::{NonZeroU32, NonZeroUsize}, sync::{mpsc::{Receiver, Sender, SyncSender}, Arc, Mutex}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}
 };
 
 use super::apriltag::{
     image::{ImageBuffer, Luma},
-    AprilTagDetector,
+    AprilTagDetector, AprilTagHandle, AprilTagPool, DetectorParams,
 };
 use fxhash::FxHashMap;
 use gputter::types::{AlignedMatrix4, AlignedVec4};
-use nalgebra::{Vector2, Vector4};
+use nalgebra::{Isometry3, Vector2, Vector4};
 pub use realsense_rust;
 use realsense_rust::{
-    config::Config, frame::{ColorFrame, DepthFrame, PixelKind}, kind::{Rs2CameraInfo, Rs2Format, Rs2StreamKind}, pipeline::{ActivePipeline, InactivePipeline}
+    base::Rs2Intrinsics, config::Config, context::Context, frame::{ColorFrame, DepthFrame, FrameEx, PixelKind}, kind::{Rs2CameraInfo, Rs2Format, Rs2StreamKind, Rs2TimestampDomain}, pipeline::{ActivePipeline, InactivePipeline}
 };
 use simple_motion::StaticImmutableNode;
 use tasker::shared::{MaybeOwned, OwnedData};
-use thalassic::{DepthProjector, DepthProjectorBuilder};
+use thalassic::{DepthConvention, DepthProjector, DepthProjectorBuilder};
 use tracing::{error, info, warn};
 
 use crate::{
@@ -26,12 +26,23 @@ use crate::{
     },
 };
 
-use super::{apriltag::Apriltag, streaming::CameraStream};
+use super::{apriltag::Apriltag, calibration::apply_correction, camera::{report_conflicts, CameraDescriptor, CameraKind}, depth_filter::{DepthFilterChain, SpatialFilterParams, TemporalDepthFilter, TemporalFilterParams}, streaming::CameraStream};
 
 pub struct DepthCameraInfo {
     pub node: StaticImmutableNode,
+    /// Applied in the camera's local frame on top of the transform of `node`.
+    pub extrinsic_correction: Isometry3<f64>,
     pub ignore_apriltags: bool,
     pub stream_index: usize,
+    pub apriltag_params: DetectorParams,
+    pub min_depth: Option<f32>,
+    pub max_depth: Option<f32>,
+    pub decimation: Option<NonZeroU32>,
+    pub temporal_filter: Option<TemporalFilterParams>,
+    pub temporal_filter_frames: Option<NonZeroUsize>,
+    pub spatial_filter: Option<SpatialFilterParams>,
+    pub fill_holes: bool,
+    pub max_in_flight_frames: Option<NonZeroUsize>,
 }
 
 pub fn enumerate_depth_cameras(
@@ -39,6 +50,7 @@ pub fn enumerate_depth_cameras(
     localizer_ref: &LocalizerRef,
     serial_to_chain: impl IntoIterator<Item = (String, DepthCameraInfo)>,
     apriltags: &'static [(usize, Apriltag)],
+    apriltag_pool: &AprilTagPool,
 ) {
     let (init_tx, init_rx) = std::sync::mpsc::channel::<&'static str>();
     let (pcl_storage_channels_tx, pcl_storage_channels_rx) = std::sync::mpsc::channel();
@@ -49,8 +61,18 @@ pub fn enumerate_depth_cameras(
                 serial,
                 DepthCameraInfo {
                     node,
+                    extrinsic_correction,
                     ignore_apriltags,
                     stream_index,
+                    apriltag_params,
+                    min_depth,
+                    max_depth,
+                    decimation,
+                    temporal_filter,
+                    temporal_filter_frames,
+                    spatial_filter,
+                    fill_holes,
+                    max_in_flight_frames,
                 },
             )| {
                 let Some(camera_stream) = CameraStream::new(stream_index) else {
@@ -61,6 +83,7 @@ pub fn enumerate_depth_cameras(
                 let (tx, rx) = std::sync::mpsc::sync_channel(1);
                 let pcl_storage_channels_tx = pcl_storage_channels_tx.clone();
                 let init_tx = init_tx.clone();
+                let apriltag_pool = apriltag_pool.clone();
 
                 std::thread::spawn(move || {
                     let mut camera_task = DepthCameraTask {
@@ -69,9 +92,20 @@ pub fn enumerate_depth_cameras(
                         camera_stream,
                         state: OnceCell::new(),
                         apriltags,
+                        apriltag_pool,
                         localizer_ref,
                         node,
+                        extrinsic_correction,
                         ignore_apriltags,
+                        apriltag_params,
+                        min_depth,
+                        max_depth,
+                        decimation,
+                        temporal_filter,
+                        temporal_filter_frames,
+                        spatial_filter,
+                        fill_holes,
+                        max_in_flight_frames,
                         pcl_storage_channels_tx: Some(pcl_storage_channels_tx),
                         init_tx
                     };
@@ -102,6 +136,9 @@ pub fn enumerate_depth_cameras(
             return;
         }
     };
+    report_conflicts(&describe_realsense_devices(&context), "serial", |camera| {
+        camera.serial.as_deref()
+    });
 
     std::thread::spawn(move || {
         loop {
@@ -208,11 +245,195 @@ pub fn enumerate_depth_cameras(
     });
 }
 
+/// Describes every connected RealSense camera without starting any pipelines.
+pub fn list_realsense_cameras() -> Vec<CameraDescriptor> {
+    match Context::new() {
+        Ok(context) => describe_realsense_devices(&context),
+        Err(e) => {
+            error!("Failed to get RealSense Context: {e}");
+            vec![]
+        }
+    }
+}
+
+fn describe_realsense_devices(context: &Context) -> Vec<CameraDescriptor> {
+    context
+        .query_devices(HashSet::new())
+        .into_iter()
+        .filter_map(|device| {
+            let info = |info: Rs2CameraInfo| {
+                device
+                    .info(info)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from)
+            };
+            let Some(serial) = info(Rs2CameraInfo::SerialNumber) else {
+                error!("Failed to get serial number for RealSense Camera");
+                return None;
+            };
+            Some(CameraDescriptor {
+                kind: CameraKind::RealSense,
+                path: None,
+                port: info(Rs2CameraInfo::PhysicalPort),
+                serial: Some(serial),
+                name: info(Rs2CameraInfo::Name),
+                capabilities: info(Rs2CameraInfo::UsbTypeDescriptor)
+                    .map(|usb| vec![format!("usb{usb}")])
+                    .unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// Detects gaps in the frame numbers reported by a RealSense stream.
+#[derive(Default)]
+struct FrameDropDetector {
+    last_frame_number: Option<u64>,
+    dropped_frames: u64,
+}
+
+impl FrameDropDetector {
+    /// Records the given frame number and returns how many frames were dropped
+    /// since the previous one.
+    ///
+    /// A frame number that does not increase is treated as a stream restart.
+    fn observe(&mut self, frame_number: u64) -> u64 {
+        let dropped = match self.last_frame_number {
+            Some(last) if frame_number > last => frame_number - last - 1,
+            _ => 0,
+        };
+        self.last_frame_number = Some(frame_number);
+        self.dropped_frames += dropped;
+        dropped
+    }
+
+    fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+}
+
+/// How many frames a depth camera has dropped, as detected from gaps in their frame numbers.
+///
+/// Frames that are dropped steadily usually mean that the USB connection does not have
+/// enough bandwidth for the configured streams.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameDropStats {
+    pub color_frames_dropped: u64,
+    pub depth_frames_dropped: u64,
+}
+
+static FRAME_DROP_STATS: Mutex<Vec<(&'static str, FrameDropStats)>> = Mutex::new(Vec::new());
+
+fn record_frame_drops(serial: &'static str, stats: FrameDropStats) {
+    let mut all_stats = FRAME_DROP_STATS.lock().unwrap();
+    match all_stats.iter_mut().find(|(s, _)| *s == serial) {
+        Some((_, old)) => *old = stats,
+        None => all_stats.push((serial, stats)),
+    }
+}
+
+/// Returns the frame drop stats of the depth camera with the given serial, if it has received
+/// any frames.
+pub fn get_frame_drop_stats(serial: &str) -> Option<FrameDropStats> {
+    FRAME_DROP_STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(s, _)| *s == serial)
+        .map(|&(_, stats)| stats)
+}
+
+/// How many points the frames of a depth camera were projected into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DepthPointStats {
+    /// Pixels that were projected into points in the latest frame.
+    pub points_projected: usize,
+    /// Points in the latest frame that passed the validity checks of the projection.
+    ///
+    /// A camera that suddenly has close to no valid points is likely faulty or obstructed.
+    pub valid_points: usize,
+    /// Frames that have been projected in total.
+    pub frames_projected: u64,
+}
+
+impl DepthPointStats {
+    fn observe(&mut self, point_cloud: &[AlignedVec4<f32>]) {
+        self.points_projected = point_cloud.len();
+        self.valid_points = point_cloud.iter().filter(|p| p.w != 0.0).count();
+        self.frames_projected += 1;
+    }
+}
+
+static DEPTH_POINT_STATS: Mutex<Vec<(&'static str, DepthPointStats)>> = Mutex::new(Vec::new());
+/// How often each depth camera logs its [`DepthPointStats`] and [`FrameDropStats`].
+const POINT_STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+fn record_depth_points(serial: &'static str, point_cloud: &[AlignedVec4<f32>]) {
+    let mut all_stats = DEPTH_POINT_STATS.lock().unwrap();
+    let stats = match all_stats.iter_mut().position(|(s, _)| *s == serial) {
+        Some(i) => &mut all_stats[i].1,
+        None => {
+            all_stats.push((serial, DepthPointStats::default()));
+            &mut all_stats.last_mut().unwrap().1
+        }
+    };
+    stats.observe(point_cloud);
+}
+
+/// Returns the point stats of the depth camera with the given serial, if it has projected
+/// any frames.
+pub fn get_depth_point_stats(serial: &str) -> Option<DepthPointStats> {
+    DEPTH_POINT_STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(s, _)| *s == serial)
+        .map(|&(_, stats)| stats)
+}
+
+/// Waits for the first depth frame from the pipeline, and returns the intrinsics of that frame
+/// after it has been filtered.
+fn filtered_depth_intrinsics(
+    pipeline: &mut ActivePipeline,
+    depth_filters: &mut DepthFilterChain,
+    serial: &str,
+) -> Option<Rs2Intrinsics> {
+    loop {
+        let frames = match pipeline.wait(None) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Failed to get frame from RealSense Camera {serial}: {e}");
+                return None;
+            }
+        };
+        let Some(frame) = frames.frames_of_type::<DepthFrame>().into_iter().next() else {
+            continue;
+        };
+        let frame = match depth_filters.apply(frame) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Failed to filter depth from RealSense Camera {serial}: {e}");
+                return None;
+            }
+        };
+        return match frame.stream_profile().intrinsics() {
+            Ok(x) => Some(x),
+            Err(e) => {
+                error!("Failed to get filtered depth intrinsics for RealSense Camera {serial}: {e}");
+                None
+            }
+        };
+    }
+}
+
 struct DepthCameraState {
     image: MaybeOwned<ImageBuffer<Luma<u8>, Vec<u8>>>,
     depth_projector: DepthProjector,
     pcl_storage_channel: Arc<PointsStorageChannel>,
     point_cloud: Box<[AlignedVec4<f32>]>,
+    apriltag: Option<AprilTagHandle>,
+    depth_filters: Option<DepthFilterChain>,
+    frame_agreement: Option<TemporalDepthFilter>,
 }
 
 struct DepthCameraTask {
@@ -221,9 +442,20 @@ struct DepthCameraTask {
     camera_stream: CameraStream,
     state: OnceCell<DepthCameraState>,
     apriltags: &'static [(usize, Apriltag)],
+    apriltag_pool: AprilTagPool,
     localizer_ref: LocalizerRef,
     node: StaticImmutableNode,
+    extrinsic_correction: Isometry3<f64>,
     ignore_apriltags: bool,
+    apriltag_params: DetectorParams,
+    min_depth: Option<f32>,
+    max_depth: Option<f32>,
+    decimation: Option<NonZeroU32>,
+    temporal_filter: Option<TemporalFilterParams>,
+    temporal_filter_frames: Option<NonZeroUsize>,
+    spatial_filter: Option<SpatialFilterParams>,
+    fill_holes: bool,
+    max_in_flight_frames: Option<NonZeroUsize>,
     pcl_storage_channels_tx: Option<Sender<Arc<PointsStorageChannel>>>,
     init_tx: Sender<&'static str>
 }
@@ -268,7 +500,7 @@ impl DepthCameraTask {
             }
         }
 
-        let Some(depth_format) = depth_format else {
+        let Some(mut depth_format) = depth_format else {
             error!("Depth stream missing after initialization of {}", self.serial);
             return;
         };
@@ -277,7 +509,7 @@ impl DepthCameraTask {
             return;
         };
 
-        let DepthCameraState { image, depth_projector, pcl_storage_channel, point_cloud  } = if let Some(state) = self.state.get_mut() {
+        let DepthCameraState { image, depth_projector, pcl_storage_channel, point_cloud, apriltag, depth_filters, frame_agreement } = if let Some(state) = self.state.get_mut() {
             if state.image.width() as usize != color_format.width() || state.image.height() as usize != color_format.height() {
                 warn!("RealSense Color Camera {} format changed", self.serial);
                 return;
@@ -289,25 +521,55 @@ impl DepthCameraTask {
                 color_format.height() as u32,
                 Luma([0]),
             ));
-            if !self.ignore_apriltags {
+            let apriltag = if !self.ignore_apriltags {
                 let mut det = AprilTagDetector::new(
                     color_format.fx() as f64,
                     color_format.fy() as f64,
                     color_format.width() as u32,
                     color_format.height() as u32,
                     image.create_lendee(),
+                    self.apriltag_params,
                 );
                 for (tag_id, tag) in self.apriltags {
                     det.add_tag(tag.tag_position, tag.get_quat(), tag.tag_width, *tag_id);
                 }
                 let localizer_ref = self.localizer_ref.clone();
-                let mut inverse_local = self.node.get_local_isometry();
+                let mut inverse_local =
+                    apply_correction(self.node.get_local_isometry(), self.extrinsic_correction);
                 inverse_local.inverse_mut();
                 det.detection_callbacks_ref().add_fn(move |observation| {
-                    localizer_ref
-                        .set_april_tag_isometry(inverse_local * observation.get_isometry_of_observer());
+                    localizer_ref.set_april_tag_isometry(
+                        inverse_local * observation.get_isometry_of_observer(),
+                        observation.captured_at,
+                    );
                 });
-                std::thread::spawn(move || det.run());
+                Some(self.apriltag_pool.add_detector(det))
+            } else {
+                None
+            };
+
+            let decimation = self.decimation.unwrap_or(NonZeroU32::MIN);
+            let mut depth_filters = match DepthFilterChain::new(
+                decimation,
+                self.spatial_filter,
+                self.temporal_filter,
+                self.fill_holes,
+            ) {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("Failed to create depth filters for RealSense Camera {}: {e}", self.serial);
+                    return;
+                }
+            };
+            let mut projector_decimation = decimation;
+            if let Some(depth_filters) = &mut depth_filters {
+                // Filtered frames have already been decimated, so the intrinsics are taken
+                // from the first filtered frame instead
+                let Some(intrinsics) = filtered_depth_intrinsics(&mut pipeline, depth_filters, self.serial) else {
+                    return;
+                };
+                depth_format = intrinsics;
+                projector_decimation = NonZeroU32::MIN;
             }
 
             let focal_length_px;
@@ -318,17 +580,21 @@ impl DepthCameraTask {
             } else {
                 focal_length_px = depth_format.fx();
             }
+            let principal_point_px = Vector2::new(depth_format.ppx(), depth_format.ppy());
+            let image_size = Vector2::new(depth_format.width(), depth_format.height());
             let depth_projecter_builder = DepthProjectorBuilder {
-                image_size: Vector2::new(
-                    NonZeroU32::new(depth_format.width() as u32).unwrap(),
-                    NonZeroU32::new(depth_format.height() as u32).unwrap(),
-                ),
+                image_size: image_size.map(|n| NonZeroU32::new(n as u32).unwrap()),
                 focal_length_px,
-                principal_point_px: Vector2::new(depth_format.ppx(), depth_format.ppy()),
+                principal_point_px,
+                convention: DepthConvention::YDownRayDistance,
+                min_depth: self.min_depth,
+                max_depth: self.max_depth,
+                decimation: projector_decimation,
             };
-            let pcl_storage = depth_projecter_builder.make_points_storage();
-            let pcl_storage_channel = Arc::new(PointsStorageChannel::new_for(&pcl_storage));
-            pcl_storage_channel.set_projected(pcl_storage);
+            let pcl_storage_channel = Arc::new(PointsStorageChannel::new(
+                depth_projecter_builder,
+                self.max_in_flight_frames.unwrap_or(NonZeroUsize::MIN),
+            ));
             if let Some(pcl_storage_channels_tx) = self.pcl_storage_channels_tx.take() {
                 let _ = pcl_storage_channels_tx.send(pcl_storage_channel.clone());
             }
@@ -343,12 +609,21 @@ impl DepthCameraTask {
                 ).collect(),
                 depth_projector,
                 pcl_storage_channel,
+                apriltag,
+                depth_filters,
+                frame_agreement: self.temporal_filter_frames.map(|frame_count| {
+                    TemporalDepthFilter::new(frame_count, image_size.x * image_size.y)
+                }),
             });
             self.state.get_mut().unwrap()
         };
         
         info!("RealSense Camera {} opened", self.serial);
 
+        let mut color_drops = FrameDropDetector::default();
+        let mut depth_drops = FrameDropDetector::default();
+        let mut next_stats_at = Instant::now() + POINT_STATS_INTERVAL;
+
         loop {
             let frames = match pipeline.wait(None) {
                 Ok(x) => x,
@@ -357,8 +632,19 @@ impl DepthCameraTask {
                     break;
                 }
             };
+            let received_at = Instant::now();
 
             for frame in frames.frames_of_type::<ColorFrame>() {
+                let captured_at =
+                    frame_captured_at(frame.timestamp(), frame.timestamp_domain(), received_at);
+                let dropped = color_drops.observe(frame.frame_number() as u64);
+                if dropped > 0 {
+                    warn!(
+                        "RealSense Camera {} dropped {dropped} color frames ({} total)",
+                        self.serial,
+                        color_drops.dropped_frames()
+                    );
+                }
                 // This is a bug in RealSense. It will say the pixel kind is BGR8 when it is actually RGB8.
                 if !matches!(frame.get(0, 0), Some(PixelKind::Bgr8 { .. })) {
                     error!("Unexpected color pixel kind: {:?}", frame.get(0, 0));
@@ -381,6 +667,9 @@ impl DepthCameraTask {
                             *dst = new;
                         });
                     image.share();
+                    if let Some(apriltag) = apriltag.as_ref() {
+                        apriltag.detect_shared(captured_at);
+                    }
                 }
 
                 self.camera_stream
@@ -394,9 +683,29 @@ impl DepthCameraTask {
 
             let observe_depth = get_observe_depth();
             for frame in frames.frames_of_type::<DepthFrame>() {
+                let dropped = depth_drops.observe(frame.frame_number() as u64);
+                if dropped > 0 {
+                    warn!(
+                        "RealSense Camera {} dropped {dropped} depth frames ({} total)",
+                        self.serial,
+                        depth_drops.dropped_frames()
+                    );
+                }
+                // Frames are still received while paused so the pipeline does not back up
                 if !observe_depth {
                     continue;
                 }
+                let frame = if let Some(depth_filters) = depth_filters.as_mut() {
+                    match depth_filters.apply(frame) {
+                        Ok(x) => x,
+                        Err(e) => {
+                            error!("Failed to filter depth from RealSense Camera {}: {e}", self.serial);
+                            continue;
+                        }
+                    }
+                } else {
+                    frame
+                };
                 if !matches!(frame.get(0, 0), Some(PixelKind::Z16 { .. })) {
                     error!("Unexpected depth pixel kind: {:?}", frame.get(0, 0));
                 }
@@ -404,12 +713,16 @@ impl DepthCameraTask {
                 debug_assert_eq!(frame.width() * frame.height() * 2, frame.get_data_size());
                 unsafe {
                     let data: *const _ = frame.get_data();
-                    let slice = std::slice::from_raw_parts(
+                    let mut slice = std::slice::from_raw_parts(
                         data.cast::<u16>(),
                         frame.width() * frame.height(),
                     );
+                    if let Some(frame_agreement) = frame_agreement.as_mut() {
+                        slice = frame_agreement.filter(slice);
+                    }
 
-                    let camera_transform = self.node.get_global_isometry();
+                    let camera_transform =
+                        apply_correction(self.node.get_global_isometry(), self.extrinsic_correction);
                     let camera_transform: AlignedMatrix4<f32> =
                         camera_transform.to_homogeneous().cast::<f32>().into();
                     let Some(mut pcl_storage) = pcl_storage_channel.get_finished() else {
@@ -425,11 +738,161 @@ impl DepthCameraTask {
                     pcl_storage =
                         depth_projector.project(slice, &camera_transform, pcl_storage, depth_scale);
                     pcl_storage.read(point_cloud);
+                    record_depth_points(self.serial, point_cloud);
                     pcl_storage_channel.set_projected(pcl_storage);
                 }
             }
+            record_frame_drops(
+                self.serial,
+                FrameDropStats {
+                    color_frames_dropped: color_drops.dropped_frames(),
+                    depth_frames_dropped: depth_drops.dropped_frames(),
+                },
+            );
+
+            if received_at >= next_stats_at {
+                next_stats_at = received_at + POINT_STATS_INTERVAL;
+                if let Some(stats) = get_depth_point_stats(self.serial) {
+                    info!(
+                        serial = self.serial,
+                        points_projected = stats.points_projected,
+                        valid_points = stats.valid_points,
+                        frames_projected = stats.frames_projected,
+                        "Depth point stats"
+                    );
+                }
+                if let Some(stats) = get_frame_drop_stats(self.serial) {
+                    info!(
+                        serial = self.serial,
+                        color_frames_dropped = stats.color_frames_dropped,
+                        depth_frames_dropped = stats.depth_frames_dropped,
+                        "Frame drop stats"
+                    );
+                }
+            }
         }
 
         error!("RealSense Camera {} closed", self.serial);
     }
-}
\ No newline at end of file
+}
+
+/// Finds when a RealSense frame was captured from its timestamp in milliseconds.
+///
+/// System time and global time timestamps are relative to the unix epoch, so they are related
+/// to `now` through the current system time. Hardware clock timestamps cannot be, so those
+/// frames are assumed to have been captured `now`.
+fn frame_captured_at(timestamp_ms: f64, domain: Rs2TimestampDomain, now: Instant) -> Instant {
+    if domain == Rs2TimestampDomain::HardwareClock {
+        return now;
+    }
+    let Ok(since_epoch) = SystemTime::now().duration_since(UNIX_EPOCH) else {
+        return now;
+    };
+    Duration::try_from_secs_f64(since_epoch.as_secs_f64() - timestamp_ms / 1000.0)
+        .ok()
+        .and_then(|age| now.checked_sub(age))
+        .unwrap_or(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroU32,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use gputter::types::AlignedVec4;
+    use nalgebra::{Vector2, Vector4};
+    use realsense_rust::kind::Rs2TimestampDomain;
+    use thalassic::{DepthConvention, DepthProjectorBuilder};
+
+    use super::{
+        frame_captured_at, get_depth_point_stats, get_frame_drop_stats, record_depth_points,
+        record_frame_drops, FrameDropDetector, FrameDropStats,
+    };
+
+    #[test]
+    fn frame_drop_detection() {
+        let mut detector = FrameDropDetector::default();
+        assert_eq!(detector.observe(10), 0);
+        assert_eq!(detector.observe(11), 0);
+        assert_eq!(detector.observe(15), 3);
+        assert_eq!(detector.observe(16), 0);
+        assert_eq!(detector.dropped_frames(), 3);
+        // Restarted stream
+        assert_eq!(detector.observe(1), 0);
+        assert_eq!(detector.observe(3), 1);
+        assert_eq!(detector.dropped_frames(), 4);
+
+        assert_eq!(get_frame_drop_stats("frame_drop_detection"), None);
+        let stats = FrameDropStats {
+            color_frames_dropped: 0,
+            depth_frames_dropped: detector.dropped_frames(),
+        };
+        record_frame_drops("frame_drop_detection", stats);
+        assert_eq!(get_frame_drop_stats("frame_drop_detection"), Some(stats));
+    }
+
+    #[test]
+    fn captured_at_from_frame_timestamp() {
+        let now = Instant::now();
+        let age = Duration::from_millis(50);
+        let timestamp_ms = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap() - age)
+            .as_secs_f64()
+            * 1000.0;
+        let captured_at = frame_captured_at(timestamp_ms, Rs2TimestampDomain::GlobalTime, now);
+        // The system clock keeps running between reading it here and in the conversion
+        let error = (now - captured_at).abs_diff(age);
+        assert!(error < Duration::from_millis(20), "{error:?}");
+
+        assert_eq!(
+            frame_captured_at(timestamp_ms, Rs2TimestampDomain::HardwareClock, now),
+            now
+        );
+    }
+
+    #[test]
+    fn depth_point_stats() {
+        let builder = DepthProjectorBuilder {
+            image_size: Vector2::new(NonZeroU32::new(4).unwrap(), NonZeroU32::new(2).unwrap()),
+            focal_length_px: 2.0,
+            principal_point_px: Vector2::new(2.0, 1.0),
+            convention: DepthConvention::YDownRayDistance,
+            min_depth: Some(0.5),
+            max_depth: Some(3.0),
+            decimation: NonZeroU32::MIN,
+        };
+        // No depth, too close, and too far pixels are not valid
+        let depths = [0.0, 1.0, 0.2, 2.0, 5.0, 1.5, 3.0, 0.0];
+        let point_cloud: Vec<_> = depths
+            .into_iter()
+            .enumerate()
+            .map(|(i, depth)| {
+                let pixel = Vector2::new(i as u32 % 4, i as u32 / 4);
+                let w = if builder.is_depth_valid(depth) {
+                    1.0
+                } else {
+                    0.0
+                };
+                AlignedVec4::from(builder.project_pixel(pixel, depth).push(w))
+            })
+            .collect();
+        assert_eq!(point_cloud.iter().filter(|p| p.w != 0.0).count(), 4);
+
+        assert_eq!(get_depth_point_stats("depth_point_stats"), None);
+        record_depth_points("depth_point_stats", &point_cloud);
+        record_depth_points("depth_point_stats", &point_cloud);
+        let stats = get_depth_point_stats("depth_point_stats").unwrap();
+        assert_eq!(stats.points_projected, 8);
+        assert_eq!(stats.valid_points, 4);
+        assert_eq!(stats.frames_projected, 2);
+
+        record_depth_points(
+            "depth_point_stats",
+            &[AlignedVec4::from(Vector4::default()); 8],
+        );
+        let stats = get_depth_point_stats("depth_point_stats").unwrap();
+        assert_eq!(stats.valid_points, 0);
+        assert_eq!(stats.frames_projected, 3);
+    }
+}
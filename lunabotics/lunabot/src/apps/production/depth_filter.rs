@@ -0,0 +1,389 @@
+use std::{
+    collections::VecDeque,
+    ffi::CStr,
+    num::{NonZeroU32, NonZeroUsize},
+    ptr::{null_mut, NonNull},
+};
+
+use anyhow::anyhow;
+use realsense_rust::frame::{DepthFrame, FrameEx};
+use realsense_sys as sys;
+use serde::Deserialize;
+
+/// How long to wait for a processing block to output a filtered frame.
+const PROCESSING_TIMEOUT_MS: u32 = 1000;
+/// Depths within this fraction of their average are considered to agree.
+const TEMPORAL_FILTER_MAX_SPREAD: f32 = 0.05;
+
+/// Parameters for the RealSense edge-preserving spatial filter.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct SpatialFilterParams {
+    /// How much a pixel is weighted against its neighbours, from 0.25 to 1. Lower values
+    /// smooth more.
+    pub smooth_alpha: f32,
+    /// Neighbouring depths that differ by more than this many depth units are not averaged
+    /// together, so that edges between objects are preserved.
+    pub smooth_delta: f32,
+    /// How many times the filter is applied, from 1 to 5.
+    pub iterations: u8,
+}
+
+impl Default for SpatialFilterParams {
+    fn default() -> Self {
+        Self {
+            smooth_alpha: 0.5,
+            smooth_delta: 20.0,
+            iterations: 2,
+        }
+    }
+}
+
+/// Parameters for the RealSense temporal filter.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct TemporalFilterParams {
+    /// How much the current frame is weighted against previous frames, from 0 to 1. Lower
+    /// values smooth more.
+    pub smooth_alpha: f32,
+    /// Depths that differ from the previous frame by more than this many depth units are not
+    /// averaged with it.
+    pub smooth_delta: f32,
+    /// When a pixel with no depth is filled in with its last valid depth, from 0 (never) to
+    /// 8 (always). The default of 3 fills pixels that were valid in 2 of the last 4 frames.
+    pub persistency: u8,
+}
+
+impl Default for TemporalFilterParams {
+    fn default() -> Self {
+        Self {
+            smooth_alpha: 0.4,
+            smooth_delta: 20.0,
+            persistency: 3,
+        }
+    }
+}
+
+/// Turns an error returned by librealsense into a result, freeing the error.
+fn check(error: *mut sys::rs2_error) -> anyhow::Result<()> {
+    if error.is_null() {
+        return Ok(());
+    }
+    let message = unsafe {
+        let message = CStr::from_ptr(sys::rs2_get_error_message(error))
+            .to_string_lossy()
+            .into_owned();
+        sys::rs2_free_error(error);
+        message
+    };
+    Err(anyhow!(message))
+}
+
+/// A single stage of a [`DepthFilterChain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum DepthFilterKind {
+    Decimation,
+    Spatial,
+    Temporal,
+    HoleFill,
+}
+
+/// A RealSense SDK processing block, along with the queue that its output is received from.
+struct ProcessingBlock {
+    kind: DepthFilterKind,
+    block: NonNull<sys::rs2_processing_block>,
+    queue: NonNull<sys::rs2_frame_queue>,
+}
+
+impl ProcessingBlock {
+    fn new(kind: DepthFilterKind, options: &[(sys::rs2_option, f32)]) -> anyhow::Result<Self> {
+        let mut error = null_mut();
+        let block = unsafe {
+            match kind {
+                DepthFilterKind::Decimation => sys::rs2_create_decimation_filter_block(&mut error),
+                DepthFilterKind::Spatial => sys::rs2_create_spatial_filter_block(&mut error),
+                DepthFilterKind::Temporal => sys::rs2_create_temporal_filter_block(&mut error),
+                DepthFilterKind::HoleFill => sys::rs2_create_hole_filling_filter_block(&mut error),
+            }
+        };
+        check(error)?;
+        let block =
+            NonNull::new(block).ok_or_else(|| anyhow!("Failed to create {kind:?} filter"))?;
+
+        let queue = unsafe { sys::rs2_create_frame_queue(1, &mut error) };
+        if let Err(e) = check(error) {
+            unsafe { sys::rs2_delete_processing_block(block.as_ptr()) };
+            return Err(e);
+        }
+        let Some(queue) = NonNull::new(queue) else {
+            unsafe { sys::rs2_delete_processing_block(block.as_ptr()) };
+            return Err(anyhow!("Failed to create queue for {kind:?} filter"));
+        };
+        // From here on the block and queue are deleted when dropped
+        let block = Self { kind, block, queue };
+
+        unsafe {
+            sys::rs2_start_processing_queue(block.block.as_ptr(), block.queue.as_ptr(), &mut error);
+        }
+        check(error)?;
+        for &(option, value) in options {
+            unsafe {
+                sys::rs2_set_option(block.block.as_ptr().cast(), option, value, &mut error);
+            }
+            check(error)?;
+        }
+        Ok(block)
+    }
+
+    /// Takes ownership of the given frame and returns the processed frame.
+    fn process(
+        &mut self,
+        frame: NonNull<sys::rs2_frame>,
+    ) -> anyhow::Result<NonNull<sys::rs2_frame>> {
+        let mut error = null_mut();
+        unsafe {
+            sys::rs2_process_frame(self.block.as_ptr(), frame.as_ptr(), &mut error);
+        }
+        check(error)?;
+        let output = unsafe {
+            sys::rs2_wait_for_frame(self.queue.as_ptr(), PROCESSING_TIMEOUT_MS, &mut error)
+        };
+        check(error)?;
+        NonNull::new(output).ok_or_else(|| anyhow!("{:?} filter did not output a frame", self.kind))
+    }
+}
+
+impl Drop for ProcessingBlock {
+    fn drop(&mut self) {
+        unsafe {
+            sys::rs2_delete_processing_block(self.block.as_ptr());
+            sys::rs2_delete_frame_queue(self.queue.as_ptr());
+        }
+    }
+}
+
+// The block and queue are only ever used by the camera thread that owns them
+unsafe impl Send for ProcessingBlock {}
+
+/// Depth post-processing done by the RealSense SDK before a frame is projected.
+///
+/// Stages always run in the order recommended by RealSense: decimation, spatial,
+/// temporal, then hole filling.
+pub(super) struct DepthFilterChain {
+    blocks: Vec<ProcessingBlock>,
+}
+
+impl DepthFilterChain {
+    /// Returns `None` if no filters are enabled.
+    ///
+    /// Decimation on its own is left to the GPU, so it alone does not create a chain.
+    pub(super) fn new(
+        decimation: NonZeroU32,
+        spatial: Option<SpatialFilterParams>,
+        temporal: Option<TemporalFilterParams>,
+        fill_holes: bool,
+    ) -> anyhow::Result<Option<Self>> {
+        if spatial.is_none() && temporal.is_none() && !fill_holes {
+            return Ok(None);
+        }
+        let mut blocks = vec![];
+        if decimation.get() > 1 {
+            blocks.push(ProcessingBlock::new(
+                DepthFilterKind::Decimation,
+                &[(
+                    sys::rs2_option_RS2_OPTION_FILTER_MAGNITUDE,
+                    decimation.get() as f32,
+                )],
+            )?);
+        }
+        if let Some(params) = spatial {
+            blocks.push(ProcessingBlock::new(
+                DepthFilterKind::Spatial,
+                &[
+                    (
+                        sys::rs2_option_RS2_OPTION_FILTER_MAGNITUDE,
+                        params.iterations as f32,
+                    ),
+                    (
+                        sys::rs2_option_RS2_OPTION_FILTER_SMOOTH_ALPHA,
+                        params.smooth_alpha,
+                    ),
+                    (
+                        sys::rs2_option_RS2_OPTION_FILTER_SMOOTH_DELTA,
+                        params.smooth_delta,
+                    ),
+                ],
+            )?);
+        }
+        if let Some(params) = temporal {
+            blocks.push(ProcessingBlock::new(
+                DepthFilterKind::Temporal,
+                &[
+                    (
+                        sys::rs2_option_RS2_OPTION_FILTER_SMOOTH_ALPHA,
+                        params.smooth_alpha,
+                    ),
+                    (
+                        sys::rs2_option_RS2_OPTION_FILTER_SMOOTH_DELTA,
+                        params.smooth_delta,
+                    ),
+                    (
+                        sys::rs2_option_RS2_OPTION_HOLES_FILL,
+                        params.persistency as f32,
+                    ),
+                ],
+            )?);
+        }
+        if fill_holes {
+            // Fill from the left
+            blocks.push(ProcessingBlock::new(
+                DepthFilterKind::HoleFill,
+                &[(sys::rs2_option_RS2_OPTION_HOLES_FILL, 0.0)],
+            )?);
+        }
+        Ok(Some(Self { blocks }))
+    }
+
+    /// The stages of the chain, in the order they are applied.
+    pub(super) fn kinds(&self) -> impl Iterator<Item = DepthFilterKind> + '_ {
+        self.blocks.iter().map(|block| block.kind)
+    }
+
+    /// Runs every filter over the given depth frame, returning the filtered frame.
+    ///
+    /// Decimated frames are smaller than the original, and their intrinsics are in the
+    /// stream profile of the filtered frame.
+    pub(super) fn apply(&mut self, frame: DepthFrame) -> anyhow::Result<DepthFrame> {
+        let mut frame = unsafe { frame.get_owned_raw() };
+        for block in &mut self.blocks {
+            frame = block.process(frame)?;
+        }
+        DepthFrame::try_from(frame)
+    }
+}
+
+/// Suppresses depth noise by only keeping pixels that agree across the last few frames.
+///
+/// Unlike the RealSense temporal filter, which smooths each pixel towards its history, this
+/// drops any pixel that is not stable, so it runs after the [`DepthFilterChain`].
+pub(super) struct TemporalDepthFilter {
+    history: VecDeque<Box<[u16]>>,
+    frame_count: NonZeroUsize,
+    filtered: Box<[u16]>,
+}
+
+impl TemporalDepthFilter {
+    pub(super) fn new(frame_count: NonZeroUsize, pixel_count: usize) -> Self {
+        Self {
+            history: VecDeque::with_capacity(frame_count.get()),
+            frame_count,
+            filtered: vec![0; pixel_count].into_boxed_slice(),
+        }
+    }
+
+    /// Adds a depth frame to the history and returns the filtered frame.
+    ///
+    /// A pixel is the average of its depths over the last `frame_count` frames if
+    /// all of them are valid and agree, and `0` (no depth) otherwise. Every pixel is
+    /// `0` until enough frames have been seen.
+    pub(super) fn filter(&mut self, depths: &[u16]) -> &[u16] {
+        debug_assert_eq!(depths.len(), self.filtered.len());
+        let mut frame = if self.history.len() == self.frame_count.get() {
+            self.history.pop_front().unwrap()
+        } else {
+            vec![0; depths.len()].into_boxed_slice()
+        };
+        frame.copy_from_slice(depths);
+        self.history.push_back(frame);
+
+        if self.history.len() < self.frame_count.get() {
+            self.filtered.fill(0);
+            return &self.filtered;
+        }
+
+        for (i, dst) in self.filtered.iter_mut().enumerate() {
+            let mut min = u16::MAX;
+            let mut max = 0;
+            let mut sum = 0u32;
+            for frame in &self.history {
+                let depth = frame[i];
+                min = min.min(depth);
+                max = max.max(depth);
+                sum += depth as u32;
+            }
+            let average = sum / self.frame_count.get() as u32;
+            *dst = if min == 0 || (max - min) as f32 > average as f32 * TEMPORAL_FILTER_MAX_SPREAD {
+                0
+            } else {
+                average as u16
+            };
+        }
+        &self.filtered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::{NonZeroU32, NonZeroUsize};
+
+    use super::{
+        DepthFilterChain, DepthFilterKind, SpatialFilterParams, TemporalDepthFilter,
+        TemporalFilterParams,
+    };
+
+    #[test]
+    fn temporal_filter_stabilizes_noise() {
+        let mut filter = TemporalDepthFilter::new(NonZeroUsize::new(3).unwrap(), 4);
+        // Pixel 0 is steady with a little noise, pixel 1 flickers to a phantom point,
+        // pixel 2 drops out once, and pixel 3 is never valid
+        let frames = [
+            [1000, 0, 500, 0],
+            [1010, 0, 502, 0],
+            [990, 300, 0, 0],
+            [1000, 0, 498, 0],
+            [1005, 0, 500, 0],
+            [995, 0, 501, 0],
+        ];
+
+        assert_eq!(filter.filter(&frames[0]), [0, 0, 0, 0]);
+        assert_eq!(filter.filter(&frames[1]), [0, 0, 0, 0]);
+        assert_eq!(filter.filter(&frames[2]), [1000, 0, 0, 0]);
+        assert_eq!(filter.filter(&frames[3]), [1000, 0, 0, 0]);
+        assert_eq!(filter.filter(&frames[4]), [998, 0, 0, 0]);
+        assert_eq!(filter.filter(&frames[5]), [1000, 0, 499, 0]);
+    }
+
+    #[test]
+    fn filter_chain_order() {
+        let decimation = NonZeroU32::new(2).unwrap();
+        assert!(DepthFilterChain::new(decimation, None, None, false)
+            .unwrap()
+            .is_none());
+
+        let chain = DepthFilterChain::new(
+            decimation,
+            Some(SpatialFilterParams::default()),
+            Some(TemporalFilterParams::default()),
+            true,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            chain.kinds().collect::<Vec<_>>(),
+            [
+                DepthFilterKind::Decimation,
+                DepthFilterKind::Spatial,
+                DepthFilterKind::Temporal,
+                DepthFilterKind::HoleFill,
+            ]
+        );
+
+        let chain = DepthFilterChain::new(NonZeroU32::MIN, None, None, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            chain.kinds().collect::<Vec<_>>(),
+            [DepthFilterKind::HoleFill]
+        );
+    }
+}
@@ -2,7 +2,7 @@ use std::{
     io::{ErrorKind, Read},
     net::{SocketAddr, UdpSocket},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         OnceLock,
     },
     time::{Duration, Instant},
@@ -22,6 +22,8 @@ const CAMERA_COL_COUNT: usize = 3;
 const CAMERA_ROW_COUNT: usize = 2;
 pub const CAMERA_RESOLUTION: Vector2<u32> = Vector2::new(640, 360);
 const KEYFRAME_INTERVAL: usize = 60;
+/// How often the stats of each camera stream are logged.
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
 
 struct ImgPtr(*mut u8, usize);
 
@@ -30,6 +32,44 @@ unsafe impl Sync for ImgPtr {}
 
 static CAMERA_STREAMS: RwLock<ImgPtr> = RwLock::new(ImgPtr(std::ptr::null_mut(), 0));
 static CAMERA_STREAM_LOCKS: OnceLock<Box<[AtomicBool]>> = OnceLock::new();
+static CAMERA_STREAM_STATS: [CameraStreamCounters; CAMERA_COL_COUNT * CAMERA_ROW_COUNT] =
+    [const { CameraStreamCounters::new() }; CAMERA_COL_COUNT * CAMERA_ROW_COUNT];
+
+struct CameraStreamCounters {
+    frames_written: AtomicU64,
+    bytes_written: AtomicU64,
+    frames_dropped: AtomicU64,
+}
+
+impl CameraStreamCounters {
+    const fn new() -> Self {
+        Self {
+            frames_written: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            frames_dropped: AtomicU64::new(0),
+        }
+    }
+}
+
+/// A snapshot of how many frames were given to a camera stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CameraStreamStats {
+    /// Frames that were copied into the streamed image.
+    pub frames_written: u64,
+    pub bytes_written: u64,
+    /// Frames that were discarded because the stream was not running or was busy encoding.
+    pub frames_dropped: u64,
+}
+
+/// Returns the stats of the camera stream with the given index.
+pub fn get_camera_stream_stats(index: usize) -> Option<CameraStreamStats> {
+    let counters = CAMERA_STREAM_STATS.get(index)?;
+    Some(CameraStreamStats {
+        frames_written: counters.frames_written.load(Ordering::Relaxed),
+        bytes_written: counters.bytes_written.load(Ordering::Relaxed),
+        frames_dropped: counters.frames_dropped.load(Ordering::Relaxed),
+    })
+}
 
 pub struct CameraStream {
     index: usize,
@@ -57,11 +97,30 @@ impl CameraStream {
         Some(Self { index })
     }
 
-    pub fn write(&mut self, mut src: impl Read) -> std::io::Result<()> {
+    pub fn write(&mut self, src: impl Read) -> std::io::Result<()> {
         let Some(camera_streams) = CAMERA_STREAMS.try_read() else {
+            CAMERA_STREAM_STATS[self.index]
+                .frames_dropped
+                .fetch_add(1, Ordering::Relaxed);
             return Ok(());
         };
-        let ptr = camera_streams.0;
+        if camera_streams.0.is_null() {
+            // Streaming has not started
+            CAMERA_STREAM_STATS[self.index]
+                .frames_dropped
+                .fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        unsafe { self.write_into(camera_streams.0, src) }
+    }
+
+    /// Copies a frame into this stream's part of the combined frame at `ptr`.
+    ///
+    /// # Safety
+    /// `ptr` must point to a combined frame of every camera stream, and no other stream with
+    /// the same index may be writing to it.
+    unsafe fn write_into(&mut self, ptr: *mut u8, mut src: impl Read) -> std::io::Result<()> {
+        let counters = &CAMERA_STREAM_STATS[self.index];
         let cam_x = self.index % CAMERA_COL_COUNT;
         let cam_y = self.index / CAMERA_COL_COUNT;
         let individual_frame_row_length = CAMERA_RESOLUTION.x as usize * 3;
@@ -79,6 +138,11 @@ impl CameraStream {
             };
             src.read_exact(row)?;
         }
+        counters.frames_written.fetch_add(1, Ordering::Relaxed);
+        counters.bytes_written.fetch_add(
+            individual_frame_row_length as u64 * CAMERA_RESOLUTION.y as u64,
+            Ordering::Relaxed,
+        );
         Ok(())
     }
 }
@@ -185,6 +249,7 @@ pub fn camera_streaming(lunabase_streaming_address: SocketAddr) {
         let mut now = Instant::now();
         let sleeper = SpinSleeper::default();
         let mut keyframe = 0usize;
+        let mut next_stats_at = now + STATS_INTERVAL;
 
         info!(
             "Starting camera streaming with resolution: {}x{}",
@@ -194,6 +259,25 @@ pub fn camera_streaming(lunabase_streaming_address: SocketAddr) {
 
         loop {
             now += now.elapsed();
+            if now >= next_stats_at {
+                next_stats_at = now + STATS_INTERVAL;
+                for index in 0..CAMERA_COL_COUNT * CAMERA_ROW_COUNT {
+                    let Some(stats) = get_camera_stream_stats(index) else {
+                        continue;
+                    };
+                    if stats == CameraStreamStats::default() {
+                        // Nothing uses this stream
+                        continue;
+                    }
+                    info!(
+                        index,
+                        frames_written = stats.frames_written,
+                        bytes_written = stats.bytes_written,
+                        frames_dropped = stats.frames_dropped,
+                        "Camera stream stats"
+                    );
+                }
+            }
             keyframe = (keyframe + 1) % KEYFRAME_INTERVAL;
             if keyframe == 0 {
                 h264_enc.force_intra_frame();
@@ -248,3 +332,48 @@ pub fn camera_streaming(lunabase_streaming_address: SocketAddr) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_stats() {
+        // Not taken from `CameraStream::new`, so that the global stream is left alone
+        let mut stream = CameraStream { index: 5 };
+        let frame = vec![0u8; CAMERA_RESOLUTION.x as usize * CAMERA_RESOLUTION.y as usize * 3];
+
+        stream.write(frame.as_slice()).unwrap();
+        assert_eq!(
+            get_camera_stream_stats(5).unwrap(),
+            CameraStreamStats {
+                frames_written: 0,
+                bytes_written: 0,
+                frames_dropped: 1,
+            }
+        );
+
+        let mut buffer = vec![0u8; frame.len() * CAMERA_COL_COUNT * CAMERA_ROW_COUNT];
+        let frame = vec![1u8; frame.len()];
+        unsafe {
+            stream
+                .write_into(buffer.as_mut_ptr(), frame.as_slice())
+                .unwrap();
+            stream
+                .write_into(buffer.as_mut_ptr(), frame.as_slice())
+                .unwrap();
+        }
+        assert_eq!(
+            get_camera_stream_stats(5).unwrap(),
+            CameraStreamStats {
+                frames_written: 2,
+                bytes_written: 2 * frame.len() as u64,
+                frames_dropped: 1,
+            }
+        );
+        // Only the bottom right of the combined frame was written to
+        assert_eq!(buffer.iter().filter(|&&x| x == 1).count(), frame.len());
+        let last_row = &buffer[buffer.len() - frame.len() / CAMERA_RESOLUTION.y as usize..];
+        assert!(last_row.iter().all(|&x| x == 1));
+    }
+}
@@ -1,28 +1,36 @@
 use std::{
     cmp::Ordering,
     collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
     net::SocketAddr,
-    num::NonZeroU32,
+    num::{NonZeroU32, NonZeroUsize},
     process::Stdio,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use common::{
     lunasim::{FromLunasim, FromLunasimbot},
-    LunabotStage,
+    units::{Degrees, Radians},
+    LunabotStage, Steering,
 };
 use crossbeam::atomic::AtomicCell;
+use fxhash::FxHashMap;
 use gputter::{
     init_gputter_blocking,
     types::{AlignedMatrix4, AlignedVec4},
 };
 use lumpur::set_on_exit;
-use lunabot_ai::{run_ai, Action, Input, PollWhen};
+use lunabot_ai::{run_ai, Action, Input, PollWhen, SteeringRecorder};
 use nalgebra::{
-    Isometry3, Scale3, Transform3, UnitQuaternion, UnitVector3, Vector2, Vector3, Vector4,
+    Isometry3, Point3, Scale3, Transform3, UnitQuaternion, UnitVector3, Vector2, Vector3, Vector4,
 };
 use pathfinding::grid::Grid;
-use simple_motion::{ChainBuilder, NodeSerde};
+use serde::Deserialize;
 use tasker::shared::OwnedData;
 use tasker::tokio;
 use tasker::{
@@ -35,14 +43,20 @@ use tasker::{
     },
     BlockOn,
 };
-use thalassic::DepthProjectorBuilder;
-use tracing::{error, info, warn};
+use thalassic::{DepthConvention, DepthProjectorBuilder};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    localization::Localizer,
-    pipelines::thalassic::{get_observe_depth, spawn_thalassic_pipeline, PointsStorageChannel},
+    localization::{ImuAxisRemap, Localizer},
+    pipelines::thalassic::{
+        get_observe_depth, set_observe_depth, spawn_thalassic_pipeline, PointsStorageChannel,
+    },
+};
+use crate::{
+    pathfinding::DefaultPathfinder,
+    pipelines::thalassic::ThalassicData,
+    robot_layout::{ExpectedJoint, ExpectedLayout, RobotLayout},
 };
-use crate::{pathfinding::DefaultPathfinder, pipelines::thalassic::ThalassicData};
 
 use super::{create_packet_builder, log_teleop_messages};
 
@@ -75,15 +89,192 @@ impl LunasimStdin {
     }
 }
 
+/// Where a dry run writes the steering it recorded when lunabot exits.
+const DRY_RUN_TRACE_PATH: &str = "dry-run-steering.csv";
+
+/// Where the steering from the behavior tree goes.
+enum SimDrive {
+    Lunasim(LunasimStdin),
+    /// Steering is recorded instead of being sent to lunasim.
+    DryRun(SteeringRecorder),
+}
+
+impl SimDrive {
+    fn set_steering(&self, steering: Steering, bitcode_buffer: &mut bitcode::Buffer) {
+        match self {
+            SimDrive::Lunasim(lunasim_stdin) => {
+                let (left, right) = steering.get_left_and_right();
+                let bytes = bitcode_buffer.encode(&FromLunasimbot::Drive {
+                    left: left as f32,
+                    right: right as f32,
+                });
+                lunasim_stdin.write(bytes);
+            }
+            SimDrive::DryRun(steering_recorder) => {
+                debug!("Dry run steering: {steering:?}");
+                steering_recorder.record(steering);
+            }
+        }
+    }
+}
+
+/// Writes a recorded steering trace as CSV, with times in seconds since the first command.
+fn write_steering_trace(
+    trace: &[(Instant, Steering)],
+    mut writer: impl Write,
+) -> std::io::Result<()> {
+    writeln!(writer, "time,left,right")?;
+    let Some(&(start, _)) = trace.first() else {
+        return writer.flush();
+    };
+    for (at, steering) in trace {
+        let (left, right) = steering.get_left_and_right();
+        writeln!(
+            writer,
+            "{},{left},{right}",
+            at.duration_since(start).as_secs_f64()
+        )?;
+    }
+    writer.flush()
+}
+
 #[cfg(target_os = "windows")]
 const DELIMIT: &[u8] = b"READY\r\n";
 
 #[cfg(not(target_os = "windows"))]
 const DELIMIT: &[u8] = b"READY\n";
 
+/// The frame that points sent to lunasim in [`FromLunasimbot::PointCloud`] are in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum PointCloudFrame {
+    /// Relative to the robot's base, which is the root of the robot chain.
+    RobotBase,
+    /// The global frame, which is the frame depth is projected into.
+    #[default]
+    Global,
+}
+
+impl PointCloudFrame {
+    /// Converts projected points into this frame, discarding invalid points.
+    fn convert(
+        self,
+        points: &[AlignedVec4<f32>],
+        robot_isometry: Isometry3<f64>,
+    ) -> Box<[[f32; 3]]> {
+        let global_to_frame = match self {
+            Self::RobotBase => robot_isometry.inverse().cast::<f32>(),
+            Self::Global => Isometry3::identity(),
+        };
+        points
+            .iter()
+            .filter(|p| p.w != 0.0)
+            .map(|p| {
+                let p = global_to_frame * Point3::new(p.x, p.y, p.z);
+                [p.x, p.y, p.z]
+            })
+            .collect()
+    }
+}
+
+/// The most point clouds that can be waiting to be written to lunasim at once.
+const MAX_IN_FLIGHT_POINT_CLOUDS: usize = 2;
+
+/// Limits how many tasks can be in flight at once.
+#[derive(Clone)]
+struct InFlightLimit {
+    in_flight: Arc<AtomicUsize>,
+    max: usize,
+}
+
+/// Marks a task as in flight until dropped.
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, AtomicOrdering::AcqRel);
+    }
+}
+
+impl InFlightLimit {
+    fn new(max: usize) -> Self {
+        Self {
+            in_flight: Arc::default(),
+            max,
+        }
+    }
+
+    /// Returns `None` if the limit has been reached.
+    fn try_acquire(&self) -> Option<InFlightGuard> {
+        self.in_flight
+            .fetch_update(AtomicOrdering::AcqRel, AtomicOrdering::Acquire, |n| {
+                (n < self.max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(InFlightGuard(self.in_flight.clone()))
+    }
+
+    #[cfg(test)]
+    fn in_flight(&self) -> usize {
+        self.in_flight.load(AtomicOrdering::Acquire)
+    }
+}
+
+/// The depth camera simulated by lunasim. This must match the depth camera in lunasim.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct SimDepthCamera {
+    pub width: NonZeroU32,
+    pub height: NonZeroU32,
+    /// The field of view across the width of the depth map, in degrees.
+    pub fov_deg: f32,
+    /// Depths closer than this many meters are ignored.
+    pub near_plane: Option<f32>,
+}
+
+impl Default for SimDepthCamera {
+    fn default() -> Self {
+        Self {
+            width: NonZeroU32::new(36).unwrap(),
+            height: NonZeroU32::new(24).unwrap(),
+            fov_deg: 120.0,
+            near_plane: None,
+        }
+    }
+}
+
+impl SimDepthCamera {
+    fn fov(&self) -> Radians<f32> {
+        Degrees(self.fov_deg).into()
+    }
+
+    fn projector_builder(&self) -> DepthProjectorBuilder {
+        DepthProjectorBuilder {
+            image_size: Vector2::new(self.width, self.height),
+            focal_length_px: self.width.get() as f32 / 2.0 / (self.fov().0 / 2.0).tan(),
+            principal_point_px: Vector2::new(
+                (self.width.get() - 1) as f32 / 2.0,
+                (self.height.get() - 1) as f32 / 2.0,
+            ),
+            convention: DepthConvention::YDownRayDistance,
+            min_depth: self.near_plane,
+            max_depth: None,
+            decimation: NonZeroU32::MIN,
+        }
+    }
+}
+
 pub struct LunasimbotApp {
     pub lunabase_address: SocketAddr,
     pub max_pong_delay_ms: u64,
+    /// The least time between applying steering commands from the lunabase.
+    pub min_steering_interval_ms: u64,
+    pub point_cloud_frame: PointCloudFrame,
+    pub depth_camera: SimDepthCamera,
+    /// Record steering commands instead of sending them to lunasim.
+    pub dry_run: bool,
+    /// The axis remapping of each IMU, keyed by the IMU's id. IMUs that are not listed
+    /// are not remapped.
+    pub imu_axis_remaps: FxHashMap<String, ImuAxisRemap>,
 }
 
 impl LunasimbotApp {
@@ -238,34 +429,63 @@ impl LunasimbotApp {
                 return;
             }
         };
+        let steering_recorder = self.dry_run.then(SteeringRecorder::default);
+        let exit_recorder = steering_recorder.clone();
         let lunasim_stdin2 = lunasim_stdin.clone();
         set_on_exit(move || {
+            if let Some(exit_recorder) = &exit_recorder {
+                let result = File::create(DRY_RUN_TRACE_PATH).and_then(|file| {
+                    write_steering_trace(&exit_recorder.take(), BufWriter::new(file))
+                });
+                match result {
+                    Ok(()) => info!("Wrote dry run steering to {DRY_RUN_TRACE_PATH}"),
+                    Err(e) => error!("Failed to write dry run steering: {e}"),
+                }
+            }
             lunasim_stdin2.write(&bitcode::encode(&FromLunasimbot::Quit));
             std::process::exit(0);
         });
-        let robot_chain = NodeSerde::from_reader(
-            std::fs::File::open("robot-layout/sim.json").expect("Failed to read robot chain"),
-        )
-        .expect("Failed to parse robot chain");
-        let robot_chain = ChainBuilder::from(robot_chain).finish_static();
+        let robot_layout = match RobotLayout::load("robot-layout/sim.json") {
+            Ok(x) => x,
+            Err(e) => {
+                error!("{e:#}");
+                return;
+            }
+        };
+        let expected_layout = ExpectedLayout {
+            // The localizer sets the isometry of the root
+            root: ExpectedJoint::FREE,
+            links: vec![("depth_camera", ExpectedJoint::ANY)],
+        };
+        if let Err(e) = robot_layout.validate(&expected_layout) {
+            error!("{e:#}");
+            return;
+        }
+        let robot_chain = robot_layout.chain;
 
         let localizer = Localizer::new(robot_chain, Some(lunasim_stdin.clone()));
         let localizer_ref = localizer.get_ref();
         std::thread::spawn(|| localizer.run());
 
-        let camera_link = robot_chain.get_node_with_name("depth_camera").unwrap();
-
-        let depth_projecter_builder = DepthProjectorBuilder {
-            image_size: Vector2::new(NonZeroU32::new(36).unwrap(), NonZeroU32::new(24).unwrap()),
-            focal_length_px: 10.392,
-            principal_point_px: Vector2::new(17.5, 11.5),
+        let camera_link = match robot_layout.find_link("depth_camera") {
+            Ok(x) => x,
+            Err(e) => {
+                error!("{e:#}");
+                return;
+            }
         };
-        let mut point_cloud: Box<[_]> =
-            std::iter::repeat_n(AlignedVec4::from(Vector4::default()), 36 * 24).collect();
+
+        let depth_projecter_builder = self.depth_camera.projector_builder();
         let mut depth_projecter = depth_projecter_builder.build();
-        let pcl_storage = depth_projecter_builder.make_points_storage();
-        let pcl_storage_channel = Arc::new(PointsStorageChannel::new_for(&pcl_storage));
-        pcl_storage_channel.set_projected(pcl_storage);
+        let mut point_cloud: Box<[_]> = std::iter::repeat_n(
+            AlignedVec4::from(Vector4::default()),
+            depth_projecter.get_pixel_count().get() as usize,
+        )
+        .collect();
+        let pcl_storage_channel = Arc::new(PointsStorageChannel::new(
+            depth_projecter_builder,
+            NonZeroUsize::MIN,
+        ));
 
         let mut buffer = OwnedData::from(ThalassicData::default());
         let shared_thalassic_data = buffer.create_lendee();
@@ -301,25 +521,49 @@ impl LunasimbotApp {
         };
 
         let lunasim_stdin2 = lunasim_stdin.clone();
+        let point_cloud_frame = self.point_cloud_frame;
+        let point_cloud_writes = InFlightLimit::new(MAX_IN_FLIGHT_POINT_CLOUDS);
+        let mut dropped_point_clouds = 0usize;
+        let expected_depth_count =
+            self.depth_camera.width.get() as usize * self.depth_camera.height.get() as usize;
+        // TOML keys are always strings
+        let imu_axis_remaps: FxHashMap<usize, ImuAxisRemap> = self
+            .imu_axis_remaps
+            .into_iter()
+            .filter_map(|(id, remap)| match id.parse() {
+                Ok(id) => Some((id, remap)),
+                Err(_) => {
+                    error!("Ignoring axis remap for IMU {id:?}, as it is not a valid id");
+                    None
+                }
+            })
+            .collect();
+        let imu_axis_remap = move |id: usize| imu_axis_remaps.get(&id).copied().unwrap_or_default();
         from_lunasim_ref.add_fn_mut(move |msg| match msg {
-            FromLunasim::Accelerometer {
-                id: _,
-                acceleration,
-            } => {
+            FromLunasim::Accelerometer { id, acceleration } => {
                 let acceleration = Vector3::new(
                     acceleration[0] as f64,
                     acceleration[1] as f64,
                     acceleration[2] as f64,
                 );
-                localizer_ref.set_acceleration(acceleration);
+                localizer_ref.set_acceleration(imu_axis_remap(id).remap_acceleration(acceleration));
             }
-            FromLunasim::Gyroscope { id: _, axis, angle } => {
-                localizer_ref.set_angular_velocity(axis_angle(axis, angle));
+            FromLunasim::Gyroscope { id, axis, angle } => {
+                localizer_ref.set_angular_velocity(
+                    imu_axis_remap(id).remap_angular_velocity(axis_angle(axis, angle)),
+                );
             }
             FromLunasim::DepthMap(depths) => {
                 if !get_observe_depth() {
                     return;
                 }
+                if depths.len() != expected_depth_count {
+                    error!(
+                        "Received a depth map with {} depths instead of {expected_depth_count}",
+                        depths.len()
+                    );
+                    return;
+                }
                 let camera_transform = camera_link.get_global_isometry();
                 let camera_transform: AlignedMatrix4<f32> =
                     camera_transform.to_homogeneous().cast::<f32>().into();
@@ -331,16 +575,20 @@ impl LunasimbotApp {
                 pcl_storage.read(&mut point_cloud);
                 pcl_storage_channel.set_projected(pcl_storage);
                 let msg = FromLunasimbot::PointCloud(
-                    point_cloud
-                        .iter()
-                        .filter(|p| p.w != 0.0)
-                        .map(|p| [p.x, p.y, p.z])
-                        .collect(),
+                    point_cloud_frame.convert(&point_cloud, robot_chain.get_global_isometry()),
                 );
+                let Some(guard) = point_cloud_writes.try_acquire() else {
+                    dropped_point_clouds += 1;
+                    warn!(
+                        "Dropped point cloud as lunasim is not keeping up ({dropped_point_clouds} total)"
+                    );
+                    return;
+                };
                 let lunasim_stdin2 = lunasim_stdin2.clone();
                 let bytes = bitcode::encode(&msg);
                 rayon::spawn(move || {
                     lunasim_stdin2.write(&bytes);
+                    drop(guard);
                 });
             }
             FromLunasim::ExplicitApriltag {
@@ -357,7 +605,7 @@ impl LunasimbotApp {
                     .into(),
                     axis_angle(robot_axis, robot_angle),
                 );
-                localizer_ref.set_april_tag_isometry(isometry);
+                localizer_ref.set_april_tag_isometry(isometry, Instant::now());
             }
         });
 
@@ -375,30 +623,33 @@ impl LunasimbotApp {
 
         let lunabot_stage = Arc::new(AtomicCell::new(LunabotStage::SoftStop));
 
-        let (packet_builder, mut from_lunabase_rx, mut connected) = create_packet_builder(
+        let (_packet_builder, mut from_lunabase_rx, mut connected) = create_packet_builder(
             self.lunabase_address,
             lunabot_stage.clone(),
             self.max_pong_delay_ms,
         );
 
         let mut bitcode_buffer = bitcode::Buffer::new();
+        let drive = match steering_recorder {
+            Some(steering_recorder) => SimDrive::DryRun(steering_recorder),
+            None => SimDrive::Lunasim(lunasim_stdin.clone()),
+        };
+        let lunasim_stdin2 = lunasim_stdin.clone();
+        // The part of the latest path that was planned through free cells
+        let mut planned_path = 0..0;
 
         run_ai(
             robot_chain.into(),
+            Duration::from_millis(self.min_steering_interval_ms),
             |action, inputs| match action {
                 Action::SetStage(stage) => {
                     lunabot_stage.store(stage);
                 }
                 Action::SetSteering(steering) => {
-                    let (left, right) = steering.get_left_and_right();
-                    let bytes = bitcode_buffer.encode(&FromLunasimbot::Drive {
-                        left: left as f32,
-                        right: right as f32,
-                    });
-                    lunasim_stdin.write(bytes);
+                    drive.set_steering(steering, &mut bitcode_buffer);
                 }
                 Action::CalculatePath { from, to, mut into } => {
-                    pathfinder.pathfind(&shared_thalassic_data, from, to, &mut into);
+                    planned_path = pathfinder.pathfind(&shared_thalassic_data, from, to, &mut into);
                     let bytes = bitcode_buffer.encode(&FromLunasimbot::Path(
                         into.iter()
                             .map(|p| p.coords.cast::<f32>().data.0[0])
@@ -407,6 +658,15 @@ impl LunasimbotApp {
                     lunasim_stdin.write(bytes);
                     inputs.push(Input::PathCalculated(into));
                 }
+                Action::CheckPath { from, path } => {
+                    let path = path.get(planned_path.clone()).unwrap_or_default();
+                    if !pathfinder.is_path_clear(&shared_thalassic_data, from, path) {
+                        inputs.push(Input::PathBlocked);
+                    }
+                }
+                Action::SetObserveDepth(observe) => {
+                    set_observe_depth(observe);
+                }
             },
             |poll_when, inputs| {
                 let wait_disconnect = async {
@@ -466,6 +726,123 @@ impl LunasimbotApp {
                     }
                 }
             },
+            move || {
+                let bytes = bitcode::encode(&FromLunasimbot::Drive {
+                    left: 0.0,
+                    right: 0.0,
+                });
+                lunasim_stdin2.write(&bytes);
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f64::consts::FRAC_PI_2, num::NonZeroU32};
+
+    use common::Steering;
+    use gputter::types::AlignedVec4;
+    use lunabot_ai::SteeringRecorder;
+    use nalgebra::{Isometry3, Vector2, Vector3, Vector4};
+
+    use super::{write_steering_trace, InFlightLimit, PointCloudFrame, SimDepthCamera, SimDrive};
+
+    #[test]
+    fn point_cloud_frames() {
+        // The robot is 2 meters along X and turned 90 degrees left
+        let robot_isometry = Isometry3::new(
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(0.0, FRAC_PI_2, 0.0),
+        );
+        let points = [
+            // 1 meter in front of the robot
+            AlignedVec4::from(Vector4::new(1.0, 0.0, 0.0, 1.0)),
+            AlignedVec4::from(Vector4::new(5.0, 5.0, 5.0, 0.0)),
+        ];
+
+        let global = PointCloudFrame::Global.convert(&points, robot_isometry);
+        assert_eq!(*global, [[1.0, 0.0, 0.0]]);
+
+        let local = PointCloudFrame::RobotBase.convert(&points, robot_isometry);
+        assert_eq!(local.len(), 1);
+        let [x, y, z] = local[0];
+        assert!((Vector3::new(x, y, z) - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn sim_depth_camera_resolution() {
+        let builder = SimDepthCamera::default().projector_builder();
+        assert!((builder.focal_length_px - 10.392).abs() < 1e-3);
+        assert_eq!(builder.principal_point_px, Vector2::new(17.5, 11.5));
+
+        let camera = SimDepthCamera {
+            width: NonZeroU32::new(128).unwrap(),
+            height: NonZeroU32::new(96).unwrap(),
+            fov_deg: 90.0,
+            near_plane: Some(0.1),
+        };
+        let builder = camera.projector_builder();
+        assert_eq!(
+            builder.output_size(),
+            Vector2::new(camera.width, camera.height)
         );
+        assert!(!builder.is_depth_valid(0.05));
+
+        // The left edge of the depth map is half the field of view to the left
+        let point = builder.project_pixel(Vector2::new(0, 47), 1.0);
+        let angle = point.x.atan2(-point.z).to_degrees();
+        assert!((angle + 45.0).abs() < 0.5, "{angle}");
+        assert!((point.magnitude() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn in_flight_limit() {
+        let limit = InFlightLimit::new(2);
+        let mut guards = vec![];
+        let mut dropped = 0;
+        for _ in 0..100 {
+            match limit.try_acquire() {
+                Some(guard) => guards.push(guard),
+                None => dropped += 1,
+            }
+            assert!(limit.in_flight() <= 2);
+        }
+        assert_eq!(guards.len(), 2);
+        assert_eq!(dropped, 98);
+
+        guards.pop();
+        assert_eq!(limit.in_flight(), 1);
+        assert!(limit.try_acquire().is_some());
+        drop(guards);
+        assert_eq!(limit.in_flight(), 0);
+    }
+
+    #[test]
+    fn dry_run_records_steering() {
+        let steering_recorder = SteeringRecorder::default();
+        let drive = SimDrive::DryRun(steering_recorder.clone());
+        let mut bitcode_buffer = bitcode::Buffer::new();
+
+        let commands = [
+            Steering::new_left_right(1.0, 1.0),
+            Steering::new_left_right(-1.0, 1.0),
+        ];
+        for steering in commands {
+            drive.set_steering(steering, &mut bitcode_buffer);
+        }
+
+        let trace = steering_recorder.take();
+        let steering: Vec<_> = trace.iter().map(|&(_, steering)| steering).collect();
+        assert_eq!(steering, commands);
+
+        let mut csv = Vec::new();
+        write_steering_trace(&trace, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("time,left,right"));
+        assert_eq!(lines.next(), Some("0,1,1"));
+        assert!(lines.next().unwrap().ends_with(",-1,1"));
+        assert_eq!(lines.next(), None);
     }
 }
@@ -1,21 +1,25 @@
 use std::{
+    collections::VecDeque,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use common::lunasim::FromLunasimbot;
+use common::{
+    lunasim::FromLunasimbot,
+    units::{Meters, MetersPerSecond, Radians},
+};
 use crossbeam::atomic::AtomicCell;
-use nalgebra::{Isometry3, UnitQuaternion, UnitVector3, Vector3};
+use nalgebra::{Isometry3, Matrix3, UnitQuaternion, UnitVector3, Vector3};
+use serde::Deserialize;
 use simple_motion::StaticNode;
-use spin_sleep::SpinSleeper;
-use tracing::error;
+use tracing::{error, info, warn};
 
 use crate::{
     apps::LunasimStdin,
-    utils::{lerp_value, swing_twist_decomposition},
+    utils::{lerp_value, swing_twist_decomposition, FixedRate},
 };
 
 const ACCELEROMETER_LERP_SPEED: f64 = 150.0;
@@ -23,13 +27,126 @@ const LOCALIZATION_DELTA: f64 = 1.0 / 60.0;
 /// The threshold of speed in m/s for the robot to be considered in motion.
 const IN_MOTION_THRESHOLD: f64 = 0.1;
 const IN_MOTION_DURATION: f64 = 0.5;
+/// Apriltag observations captured longer ago than this are too stale to correct the pose with.
+const APRILTAG_MAX_AGE: Duration = Duration::from_millis(500);
+/// The most measurements buffered between localization steps before the oldest are dropped.
+const MAX_BUFFERED_MEASUREMENTS: usize = 64;
+
+/// Maps the axes of an IMU onto the axes of the robot, for IMUs that are not mounted
+/// in the same orientation as the robot.
+///
+/// Configured as the rows of a matrix that must be orthonormal, such as a permutation
+/// of the axes with some of them negated. The default leaves readings unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "[[f64; 3]; 3]")]
+pub struct ImuAxisRemap {
+    matrix: Matrix3<f64>,
+}
+
+impl Default for ImuAxisRemap {
+    fn default() -> Self {
+        Self {
+            matrix: Matrix3::identity(),
+        }
+    }
+}
+
+impl TryFrom<[[f64; 3]; 3]> for ImuAxisRemap {
+    type Error = String;
+
+    fn try_from(rows: [[f64; 3]; 3]) -> Result<Self, Self::Error> {
+        let matrix = Matrix3::from_row_iterator(rows.into_iter().flatten());
+        if (matrix * matrix.transpose() - Matrix3::identity()).amax() > 1e-6 {
+            return Err(format!("IMU axis remap {rows:?} is not orthonormal"));
+        }
+        Ok(Self { matrix })
+    }
+}
+
+impl ImuAxisRemap {
+    pub fn remap_acceleration(&self, acceleration: Vector3<f64>) -> Vector3<f64> {
+        self.matrix * acceleration
+    }
+
+    pub fn remap_angular_velocity(
+        &self,
+        angular_velocity: UnitQuaternion<f64>,
+    ) -> UnitQuaternion<f64> {
+        let Some((axis, angle)) = angular_velocity.axis_angle() else {
+            return angular_velocity;
+        };
+        // Rotation axes are flipped by reflections, so a remap that mirrors the axes
+        // must also reverse the direction of rotation.
+        let axis = self.matrix * axis.into_inner() * self.matrix.determinant().signum();
+        UnitQuaternion::from_axis_angle(&UnitVector3::new_normalize(axis), angle)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Measurement {
+    Acceleration(Vector3<f64>),
+    AngularVelocity(UnitQuaternion<f64>),
+    AprilTag(Isometry3<f64>),
+}
+
+/// Sensor measurements waiting to be consumed by the localizer, along with the newest
+/// measurement of each kind that has been consumed.
+///
+/// Measurements are consumed in the order they were measured rather than the order they
+/// arrived, and a measurement older than an already consumed measurement of the same kind
+/// is discarded, so that delayed data never overwrites newer data.
+#[derive(Default)]
+struct MeasurementBuffer {
+    pending: VecDeque<(Instant, Measurement)>,
+    acceleration: Option<(Vector3<f64>, Instant)>,
+    angular_velocity: Option<(UnitQuaternion<f64>, Instant)>,
+    april_tag: Option<(Isometry3<f64>, Instant)>,
+    last_april_tag_at: Option<Instant>,
+}
+
+impl MeasurementBuffer {
+    fn push(&mut self, measured_at: Instant, measurement: Measurement) {
+        if self.pending.len() == MAX_BUFFERED_MEASUREMENTS {
+            self.pending.pop_front();
+        }
+        let index = self.pending.partition_point(|&(t, _)| t <= measured_at);
+        self.pending.insert(index, (measured_at, measurement));
+    }
+
+    fn consume(&mut self) {
+        let is_newer = |measured_at: Instant, last: Option<Instant>| {
+            last.is_none_or(|last| measured_at >= last)
+        };
+        while let Some((measured_at, measurement)) = self.pending.pop_front() {
+            match measurement {
+                Measurement::Acceleration(acceleration) => {
+                    if is_newer(measured_at, self.acceleration.map(|(_, t)| t)) {
+                        self.acceleration = Some((acceleration, measured_at));
+                    }
+                }
+                Measurement::AngularVelocity(angular_velocity) => {
+                    if is_newer(measured_at, self.angular_velocity.map(|(_, t)| t)) {
+                        self.angular_velocity = Some((angular_velocity, measured_at));
+                    }
+                }
+                Measurement::AprilTag(isometry) => {
+                    if is_newer(measured_at, self.last_april_tag_at) {
+                        self.april_tag = Some((isometry, measured_at));
+                        self.last_april_tag_at = Some(measured_at);
+                    }
+                }
+            }
+        }
+    }
+}
 
 #[derive(Default)]
 struct LocalizerRefInner {
-    acceleration: AtomicCell<Vector3<f64>>,
-    angular_velocity: AtomicCell<UnitQuaternion<f64>>,
-    april_tag_isometry: AtomicCell<Option<Isometry3<f64>>>,
+    measurements: Mutex<MeasurementBuffer>,
+    ground_truth: AtomicCell<Option<Isometry3<f64>>>,
     in_motion: AtomicBool,
+    pose: AtomicCell<Isometry3<f64>>,
+    velocity: AtomicCell<Vector3<f64>>,
 }
 
 #[derive(Clone)]
@@ -38,28 +155,102 @@ pub struct LocalizerRef {
 }
 
 impl LocalizerRef {
+    fn push_measurement(&self, measured_at: Instant, measurement: Measurement) {
+        self.inner
+            .measurements
+            .lock()
+            .unwrap()
+            .push(measured_at, measurement);
+    }
+
     pub fn set_acceleration(&self, acceleration: Vector3<f64>) {
-        self.inner.acceleration.store(acceleration);
+        self.set_acceleration_at(acceleration, Instant::now());
     }
 
-    pub fn set_april_tag_isometry(&self, isometry: Isometry3<f64>) {
-        self.inner.april_tag_isometry.store(Some(isometry));
+    /// Provides an acceleration along with when it was measured.
+    pub fn set_acceleration_at(&self, acceleration: Vector3<f64>, measured_at: Instant) {
+        self.push_measurement(measured_at, Measurement::Acceleration(acceleration));
+    }
+
+    /// Provides the isometry of the robot as observed from an apriltag, along with when
+    /// the image the apriltag was observed in was captured.
+    pub fn set_april_tag_isometry(&self, isometry: Isometry3<f64>, captured_at: Instant) {
+        self.push_measurement(captured_at, Measurement::AprilTag(isometry));
     }
 
     pub fn set_angular_velocity(&self, angular_velocity: UnitQuaternion<f64>) {
-        self.inner.angular_velocity.store(angular_velocity);
+        self.set_angular_velocity_at(angular_velocity, Instant::now());
+    }
+
+    /// Provides an angular velocity along with when it was measured.
+    pub fn set_angular_velocity_at(
+        &self,
+        angular_velocity: UnitQuaternion<f64>,
+        measured_at: Instant,
+    ) {
+        self.push_measurement(measured_at, Measurement::AngularVelocity(angular_velocity));
+    }
+
+    /// Provides a known, externally measured pose of the robot.
+    ///
+    /// This is only used to log the estimation error on the next
+    /// localization step and never affects the estimate itself.
+    pub fn set_ground_truth(&self, isometry: Isometry3<f64>) {
+        self.inner.ground_truth.store(Some(isometry));
+    }
+
+    /// The latest estimate of the global pose of the robot.
+    pub fn get_pose(&self) -> Isometry3<f64> {
+        self.inner.pose.load()
+    }
+
+    /// The latest estimate of the global linear velocity of the robot.
+    pub fn get_velocity(&self) -> Vector3<MetersPerSecond<f64>> {
+        self.inner.velocity.load().map(MetersPerSecond)
+    }
+
+    /// The latest estimate of how fast the robot is moving in any direction.
+    pub fn get_speed(&self) -> MetersPerSecond<f64> {
+        MetersPerSecond(self.inner.velocity.load().magnitude())
+    }
+
+    fn consumed_measurements(&self) -> std::sync::MutexGuard<MeasurementBuffer> {
+        let mut measurements = self.inner.measurements.lock().unwrap();
+        measurements.consume();
+        measurements
     }
 
     fn acceleration(&self) -> Vector3<f64> {
-        self.inner.acceleration.load()
+        self.consumed_measurements()
+            .acceleration
+            .map(|(acceleration, _)| acceleration)
+            .unwrap_or_default()
+    }
+
+    fn april_tag_isometry(&self) -> Option<(Isometry3<f64>, Instant)> {
+        self.consumed_measurements().april_tag.take()
     }
 
-    fn april_tag_isometry(&self) -> Option<Isometry3<f64>> {
-        self.inner.april_tag_isometry.take()
+    /// When the newest IMU measurement was measured.
+    ///
+    /// The IMU is measured far more often than apriltags are observed, so this stands in for the
+    /// current time without tying the localizer to the wall clock.
+    fn latest_imu_measured_at(&self) -> Option<Instant> {
+        let measurements = self.consumed_measurements();
+        let acceleration_at = measurements.acceleration.map(|(_, t)| t);
+        let angular_velocity_at = measurements.angular_velocity.map(|(_, t)| t);
+        acceleration_at.max(angular_velocity_at)
     }
 
     fn angular_velocity(&self) -> UnitQuaternion<f64> {
-        self.inner.angular_velocity.load()
+        self.consumed_measurements()
+            .angular_velocity
+            .map(|(angular_velocity, _)| angular_velocity)
+            .unwrap_or_default()
+    }
+
+    fn ground_truth(&self) -> Option<Isometry3<f64>> {
+        self.inner.ground_truth.take()
     }
 
     // pub fn is_in_motion(&self) -> bool {
@@ -71,6 +262,8 @@ pub struct Localizer {
     root_node: StaticNode,
     lunasim_stdin: Option<LunasimStdin>,
     localizer_ref: LocalizerRef,
+    is_in_motion: bool,
+    is_in_motion_timer: f64,
 }
 
 impl Localizer {
@@ -81,6 +274,8 @@ impl Localizer {
             localizer_ref: LocalizerRef {
                 inner: Default::default(),
             },
+            is_in_motion: false,
+            is_in_motion_timer: 0.0,
         }
     }
 
@@ -88,114 +283,15 @@ impl Localizer {
         self.localizer_ref.clone()
     }
 
-    pub fn run(self) {
-        let spin_sleeper = SpinSleeper::default();
+    pub fn run(mut self) {
+        let mut rate = FixedRate::new(Duration::from_secs_f64(LOCALIZATION_DELTA));
         let mut bitcode_buffer = bitcode::Buffer::new();
-        let mut is_in_motion = false;
-        let mut is_in_motion_timer = 0.0;
 
         loop {
-            spin_sleeper.sleep(Duration::from_secs_f64(LOCALIZATION_DELTA));
-            let mut isometry = self.root_node.get_global_isometry();
-
-            'check: {
-                if isometry.translation.x.is_nan()
-                    || isometry.translation.y.is_nan()
-                    || isometry.translation.z.is_nan()
-                {
-                    error!("Robot origin is NaN");
-                } else if isometry.translation.x.is_infinite()
-                    || isometry.translation.y.is_infinite()
-                    || isometry.translation.z.is_infinite()
-                {
-                    error!("Robot origin is infinite");
-                } else if isometry.rotation.w.is_nan()
-                    || isometry.rotation.i.is_nan()
-                    || isometry.rotation.j.is_nan()
-                    || isometry.rotation.k.is_nan()
-                {
-                    error!("Robot rotation is NaN");
-                } else if isometry.rotation.w.is_infinite()
-                    || isometry.rotation.i.is_infinite()
-                    || isometry.rotation.j.is_infinite()
-                    || isometry.rotation.k.is_infinite()
-                {
-                    error!("Robot rotation is infinite");
-                } else {
-                    break 'check;
-                }
-                self.localizer_ref
-                    .inner
-                    .in_motion
-                    .store(false, Ordering::Relaxed);
-                self.root_node.set_isometry(Isometry3::identity());
-            }
-
-            let mut down_axis = UnitVector3::new_unchecked(Vector3::new(0.0, -1.0, 0.0));
-            let acceleration =
-                UnitVector3::new_normalize(isometry * self.localizer_ref.acceleration());
-            if !acceleration.x.is_finite()
-                || !acceleration.y.is_finite()
-                || !acceleration.z.is_finite()
-            {
+            rate.wait();
+            let Some(isometry) = self.step(Duration::from_secs_f64(LOCALIZATION_DELTA)) else {
                 continue;
-            }
-            let angle = down_axis.angle(&acceleration)
-                * lerp_value(LOCALIZATION_DELTA, ACCELEROMETER_LERP_SPEED);
-
-            if angle > 0.001 {
-                let cross = UnitVector3::new_normalize(down_axis.cross(&acceleration));
-                isometry.append_rotation_wrt_center_mut(&UnitQuaternion::from_axis_angle(
-                    &cross, -angle,
-                ));
-            }
-
-            down_axis = isometry.rotation * down_axis;
-
-            if let Some(tag_isometry) = self.localizer_ref.april_tag_isometry() {
-                isometry.translation = tag_isometry.translation;
-
-                let (_, new_twist) = swing_twist_decomposition(&tag_isometry.rotation, &down_axis);
-                let (old_swing, _) = swing_twist_decomposition(&isometry.rotation, &down_axis);
-                isometry.rotation = old_swing * new_twist;
-            } else {
-                let (_, twist) =
-                    swing_twist_decomposition(&self.localizer_ref.angular_velocity(), &down_axis);
-                isometry.append_rotation_wrt_center_mut(
-                    &UnitQuaternion::default()
-                        .try_slerp(&twist, LOCALIZATION_DELTA, 0.001)
-                        .unwrap_or_default(),
-                );
-            }
-
-            let currently_in_motion = (isometry.translation.vector
-                - self.root_node.get_global_isometry().translation.vector)
-                .magnitude()
-                / LOCALIZATION_DELTA
-                > IN_MOTION_THRESHOLD;
-            if is_in_motion {
-                if currently_in_motion {
-                    is_in_motion_timer = IN_MOTION_DURATION;
-                } else {
-                    is_in_motion_timer -= LOCALIZATION_DELTA;
-                    if is_in_motion_timer <= 0.0 {
-                        is_in_motion = false;
-                        self.localizer_ref
-                            .inner
-                            .in_motion
-                            .store(false, Ordering::Relaxed);
-                    }
-                }
-            } else if currently_in_motion {
-                is_in_motion = true;
-                is_in_motion_timer = IN_MOTION_DURATION;
-                self.localizer_ref
-                    .inner
-                    .in_motion
-                    .store(true, Ordering::Relaxed);
-            }
-
-            self.root_node.set_isometry(isometry);
+            };
 
             if let Some(lunasim_stdin) = &self.lunasim_stdin {
                 let (axis, angle) = isometry
@@ -220,4 +316,329 @@ impl Localizer {
             }
         }
     }
+
+    /// Advances the estimate by `delta`, and returns the new global pose of the robot, or `None`
+    /// if there was no usable acceleration to step with.
+    ///
+    /// [`Localizer::run`] calls this at a fixed rate against the wall clock. Calling it directly
+    /// instead drives the localizer from an external clock, so that simulations are
+    /// reproducible and can run faster than real time.
+    pub fn step(&mut self, delta: Duration) -> Option<Isometry3<f64>> {
+        let delta = delta.as_secs_f64();
+        let mut isometry = self.root_node.get_global_isometry();
+
+        'check: {
+            if isometry.translation.x.is_nan()
+                || isometry.translation.y.is_nan()
+                || isometry.translation.z.is_nan()
+            {
+                error!("Robot origin is NaN");
+            } else if isometry.translation.x.is_infinite()
+                || isometry.translation.y.is_infinite()
+                || isometry.translation.z.is_infinite()
+            {
+                error!("Robot origin is infinite");
+            } else if isometry.rotation.w.is_nan()
+                || isometry.rotation.i.is_nan()
+                || isometry.rotation.j.is_nan()
+                || isometry.rotation.k.is_nan()
+            {
+                error!("Robot rotation is NaN");
+            } else if isometry.rotation.w.is_infinite()
+                || isometry.rotation.i.is_infinite()
+                || isometry.rotation.j.is_infinite()
+                || isometry.rotation.k.is_infinite()
+            {
+                error!("Robot rotation is infinite");
+            } else {
+                break 'check;
+            }
+            self.localizer_ref
+                .inner
+                .in_motion
+                .store(false, Ordering::Relaxed);
+            self.root_node.set_isometry(Isometry3::identity());
+        }
+
+        let mut down_axis = UnitVector3::new_unchecked(Vector3::new(0.0, -1.0, 0.0));
+        let acceleration = UnitVector3::new_normalize(isometry * self.localizer_ref.acceleration());
+        if !acceleration.x.is_finite() || !acceleration.y.is_finite() || !acceleration.z.is_finite()
+        {
+            return None;
+        }
+        let angle = down_axis.angle(&acceleration) * lerp_value(delta, ACCELEROMETER_LERP_SPEED);
+
+        if angle > 0.001 {
+            let cross = UnitVector3::new_normalize(down_axis.cross(&acceleration));
+            isometry
+                .append_rotation_wrt_center_mut(&UnitQuaternion::from_axis_angle(&cross, -angle));
+        }
+
+        down_axis = isometry.rotation * down_axis;
+
+        let tag_isometry =
+            self.localizer_ref
+                .april_tag_isometry()
+                .and_then(|(tag_isometry, captured_at)| {
+                    let age = self
+                        .localizer_ref
+                        .latest_imu_measured_at()
+                        .map(|latest| latest.saturating_duration_since(captured_at))
+                        .unwrap_or_default();
+                    if age > APRILTAG_MAX_AGE {
+                        warn!("Ignoring apriltag observation that is {age:?} old");
+                        None
+                    } else {
+                        Some(tag_isometry)
+                    }
+                });
+
+        if let Some(tag_isometry) = tag_isometry {
+            isometry.translation = tag_isometry.translation;
+
+            let (_, new_twist) = swing_twist_decomposition(&tag_isometry.rotation, &down_axis);
+            let (old_swing, _) = swing_twist_decomposition(&isometry.rotation, &down_axis);
+            isometry.rotation = old_swing * new_twist;
+        } else {
+            let (_, twist) =
+                swing_twist_decomposition(&self.localizer_ref.angular_velocity(), &down_axis);
+            isometry.append_rotation_wrt_center_mut(
+                &UnitQuaternion::default()
+                    .try_slerp(&twist, delta, 0.001)
+                    .unwrap_or_default(),
+            );
+        }
+
+        let velocity = (isometry.translation.vector
+            - self.root_node.get_global_isometry().translation.vector)
+            / delta;
+        let currently_in_motion = velocity.magnitude() > IN_MOTION_THRESHOLD;
+        if self.is_in_motion {
+            if currently_in_motion {
+                self.is_in_motion_timer = IN_MOTION_DURATION;
+            } else {
+                self.is_in_motion_timer -= delta;
+                if self.is_in_motion_timer <= 0.0 {
+                    self.is_in_motion = false;
+                    self.localizer_ref
+                        .inner
+                        .in_motion
+                        .store(false, Ordering::Relaxed);
+                }
+            }
+        } else if currently_in_motion {
+            self.is_in_motion = true;
+            self.is_in_motion_timer = IN_MOTION_DURATION;
+            self.localizer_ref
+                .inner
+                .in_motion
+                .store(true, Ordering::Relaxed);
+        }
+
+        self.root_node.set_isometry(isometry);
+        self.localizer_ref.inner.pose.store(isometry);
+        self.localizer_ref.inner.velocity.store(velocity);
+
+        if let Some(ground_truth) = self.localizer_ref.ground_truth() {
+            let (position_error, orientation_error) = pose_error(&isometry, &ground_truth);
+            info!(
+                position_error = position_error.0,
+                orientation_error = orientation_error.0,
+                "Localization error against ground truth"
+            );
+        }
+
+        Some(isometry)
+    }
+}
+
+/// Returns the distance and the angle between the estimated pose and the ground truth pose.
+fn pose_error(
+    estimate: &Isometry3<f64>,
+    ground_truth: &Isometry3<f64>,
+) -> (Meters<f64>, Radians<f64>) {
+    let position_error =
+        (estimate.translation.vector - ground_truth.translation.vector).magnitude();
+    let orientation_error = estimate.rotation.angle_to(&ground_truth.rotation);
+    (Meters(position_error), Radians(orientation_error))
+}
+
+#[cfg(test)]
+mod tests {
+    use simple_motion::ChainBuilder;
+
+    use super::*;
+
+    #[test]
+    fn pose_error_matches_discrepancy() {
+        let truth = Isometry3::new(Vector3::new(1.0, 0.0, 2.0), Vector3::new(0.0, 0.3, 0.0));
+        let estimate = Isometry3::new(Vector3::new(1.0, 0.0, 2.5), Vector3::new(0.0, 0.2, 0.0));
+        let (position_error, orientation_error) = pose_error(&estimate, &truth);
+        assert!((position_error - Meters(0.5)).0.abs() < 1e-9);
+        assert!((orientation_error - Radians(0.1)).0.abs() < 1e-9);
+    }
+
+    #[test]
+    fn apriltag_capture_time_propagates() {
+        let localizer_ref = LocalizerRef {
+            inner: Default::default(),
+        };
+        let captured_at = Instant::now();
+        let isometry = Isometry3::translation(1.0, 2.0, 3.0);
+        localizer_ref.set_april_tag_isometry(isometry, captured_at);
+        assert_eq!(
+            localizer_ref.april_tag_isometry(),
+            Some((isometry, captured_at))
+        );
+        assert_eq!(localizer_ref.april_tag_isometry(), None);
+    }
+
+    #[test]
+    fn imu_axis_remap() {
+        // An IMU mounted on its side, with its X along the robot's -Y and its Y along
+        // the robot's X
+        let remap =
+            ImuAxisRemap::try_from([[0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]).unwrap();
+        let down = remap.remap_acceleration(Vector3::new(9.81, 0.0, 0.0));
+        assert!((down - Vector3::new(0.0, -9.81, 0.0)).magnitude() < 1e-9);
+
+        let yaw =
+            remap.remap_angular_velocity(UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.5));
+        let expected = UnitQuaternion::from_axis_angle(&-Vector3::y_axis(), 0.5);
+        assert!(yaw.angle_to(&expected) < 1e-9);
+
+        // A mirrored Z axis reverses rotations about X
+        let mirror =
+            ImuAxisRemap::try_from([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]]).unwrap();
+        let roll =
+            mirror.remap_angular_velocity(UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.5));
+        let expected = UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -0.5);
+        assert!(roll.angle_to(&expected) < 1e-9);
+
+        assert!(
+            ImuAxisRemap::try_from([[1.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]).is_err()
+        );
+    }
+
+    #[test]
+    fn pose_follows_apriltag() {
+        let mut localizer = Localizer::new(ChainBuilder::new_free().finish_static(), None);
+        let localizer_ref = localizer.get_ref();
+        let delta = Duration::from_millis(10);
+        let start = Instant::now();
+        let tag = Isometry3::translation(1.0, 0.0, 2.0);
+
+        localizer_ref.set_acceleration_at(Vector3::new(0.0, -9.81, 0.0), start);
+        localizer_ref.set_april_tag_isometry(tag, start);
+        localizer.step(delta).unwrap();
+        let pose = localizer_ref.get_pose();
+        assert!((pose.translation.vector - tag.translation.vector).magnitude() < 1e-9);
+        assert!(pose.rotation.angle() < 1e-6);
+        // Jumping to the apriltag covered 2.24m in one step
+        assert!((localizer_ref.get_speed().0 - 5.0f64.sqrt() / 0.01).abs() < 1e-6);
+
+        // Staying put at the apriltag settles to no velocity
+        localizer_ref.set_april_tag_isometry(tag, start + delta);
+        localizer.step(delta).unwrap();
+        assert_eq!(localizer_ref.get_pose(), pose);
+        assert!(localizer_ref.get_speed() < MetersPerSecond(1e-9));
+    }
+
+    #[test]
+    fn stale_apriltag_is_ignored() {
+        let mut localizer = Localizer::new(ChainBuilder::new_free().finish_static(), None);
+        let localizer_ref = localizer.get_ref();
+        let delta = Duration::from_millis(10);
+        let start = Instant::now();
+        let tag = Isometry3::translation(1.0, 0.0, 2.0);
+
+        // The IMU has moved on by a second since the image the tag was observed in
+        localizer_ref.set_acceleration_at(
+            Vector3::new(0.0, -9.81, 0.0),
+            start + Duration::from_secs(1),
+        );
+        localizer_ref.set_april_tag_isometry(tag, start);
+        let pose = localizer.step(delta).unwrap();
+        assert!(pose.translation.vector.magnitude() < 1e-9);
+
+        // An observation within the maximum age of the IMU is still used
+        localizer_ref
+            .set_april_tag_isometry(tag, start + Duration::from_secs(1) - APRILTAG_MAX_AGE);
+        let pose = localizer.step(delta).unwrap();
+        assert!((pose.translation.vector - tag.translation.vector).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn fixed_steps_follow_reference_trajectory() {
+        let mut localizer = Localizer::new(ChainBuilder::new_free().finish_static(), None);
+        let localizer_ref = localizer.get_ref();
+        let delta = Duration::from_millis(10);
+        let start = Instant::now();
+        localizer_ref.set_acceleration_at(Vector3::new(0.0, -9.81, 0.0), start);
+        localizer_ref.set_angular_velocity_at(
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 0.5),
+            start,
+        );
+
+        // Turning at 0.5 rad/s about Y, independent of how fast the steps are taken
+        for i in 1..=200u32 {
+            let pose = localizer.step(delta).unwrap();
+            let expected = UnitQuaternion::from_axis_angle(
+                &Vector3::y_axis(),
+                0.5 * (delta * i).as_secs_f64(),
+            );
+            assert!(pose.rotation.angle_to(&expected) < 1e-9, "{i}: {pose:?}");
+            assert!(pose.translation.vector.magnitude() < 1e-9);
+        }
+
+        localizer_ref
+            .set_april_tag_isometry(Isometry3::translation(1.0, 0.0, 2.0), start + delta * 200);
+        let pose = localizer.step(delta).unwrap();
+        assert!((pose.translation.vector - Vector3::new(1.0, 0.0, 2.0)).magnitude() < 1e-9);
+    }
+
+    #[test]
+    fn measurements_are_consumed_in_time_order() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let mut buffer = MeasurementBuffer::default();
+        buffer.push(at(20), Measurement::Acceleration(Vector3::x()));
+        buffer.push(at(10), Measurement::AprilTag(Isometry3::identity()));
+        buffer.push(
+            at(30),
+            Measurement::AngularVelocity(UnitQuaternion::identity()),
+        );
+        buffer.push(at(5), Measurement::Acceleration(Vector3::y()));
+        let order: Vec<_> = buffer.pending.iter().map(|&(t, _)| t).collect();
+        assert_eq!(order, [at(5), at(10), at(20), at(30)]);
+
+        let localizer_ref = LocalizerRef {
+            inner: Default::default(),
+        };
+        // The newer measurement arrives first, and is not overwritten by the delayed one
+        localizer_ref.set_acceleration_at(Vector3::x(), at(20));
+        localizer_ref.set_acceleration_at(Vector3::y(), at(10));
+        assert_eq!(localizer_ref.acceleration(), Vector3::x());
+        localizer_ref.set_acceleration_at(Vector3::z(), at(5));
+        assert_eq!(localizer_ref.acceleration(), Vector3::x());
+
+        let tag = Isometry3::translation(1.0, 0.0, 0.0);
+        let delayed_tag = Isometry3::translation(2.0, 0.0, 0.0);
+        localizer_ref.set_april_tag_isometry(tag, at(40));
+        localizer_ref.set_april_tag_isometry(delayed_tag, at(35));
+        assert_eq!(localizer_ref.april_tag_isometry(), Some((tag, at(40))));
+        localizer_ref.set_april_tag_isometry(delayed_tag, at(35));
+        assert_eq!(localizer_ref.april_tag_isometry(), None);
+    }
+
+    #[test]
+    fn ground_truth_is_consumed_once() {
+        let localizer_ref = LocalizerRef {
+            inner: Default::default(),
+        };
+        localizer_ref.set_ground_truth(Isometry3::identity());
+        assert!(localizer_ref.ground_truth().is_some());
+        assert!(localizer_ref.ground_truth().is_none());
+    }
 }
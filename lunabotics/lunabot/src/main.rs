@@ -2,7 +2,7 @@
 
 use std::net::SocketAddr;
 
-use apps::default_max_pong_delay_ms;
+use apps::{default_max_pong_delay_ms, default_min_steering_interval_ms};
 use lumpur::LumpurBuilder;
 use tracing::Level;
 
@@ -11,6 +11,7 @@ mod localization;
 mod motors;
 mod pathfinding;
 mod pipelines;
+mod robot_layout;
 mod teleop;
 mod utils;
 
@@ -20,6 +21,7 @@ lumpur::define_configuration! {
         Main {
             lunabase_address: SocketAddr,
             max_pong_delay_ms: Option<u64>,
+            min_steering_interval_ms: Option<u64>,
             lunabase_streaming_address: Option<SocketAddr>,
             lunabase_audio_streaming_address: Option<SocketAddr>,
             #[serde(default)]
@@ -28,7 +30,9 @@ lumpur::define_configuration! {
             depth_cameras: fxhash::FxHashMap<String, apps::DepthCameraInfo>,
             #[serde(default)]
             apriltags: fxhash::FxHashMap<String, apps::Apriltag>,
-            robot_layout: Option<String>
+            apriltag_workers: Option<std::num::NonZeroUsize>,
+            robot_layout: Option<String>,
+            extrinsic_calibration: Option<String>
         },
         Dataviz {
             lunabase_address: SocketAddr,
@@ -36,11 +40,21 @@ lumpur::define_configuration! {
             lunabase_data_address: Option<SocketAddr>,
             #[serde(default)]
             depth_cameras: fxhash::FxHashMap<String, apps::DepthCameraInfo>,
-            robot_layout: Option<String>
+            robot_layout: Option<String>,
+            extrinsic_calibration: Option<String>
         },
         Sim {
             lunabase_address: SocketAddr,
-            max_pong_delay_ms: Option<u64>
+            max_pong_delay_ms: Option<u64>,
+            min_steering_interval_ms: Option<u64>,
+            #[serde(default)]
+            dry_run: bool,
+            #[serde(default)]
+            point_cloud_frame: apps::PointCloudFrame,
+            #[serde(default)]
+            depth_camera: apps::SimDepthCamera,
+            #[serde(default)]
+            imu_axis_remaps: fxhash::FxHashMap<String, localization::ImuAxisRemap>
         }
     }
 }
@@ -50,7 +64,16 @@ lumpur::define_configuration! {
         Main {},
         Sim {
             lunabase_address: SocketAddr,
-            max_pong_delay_ms: Option<u64>
+            max_pong_delay_ms: Option<u64>,
+            min_steering_interval_ms: Option<u64>,
+            #[serde(default)]
+            dry_run: bool,
+            #[serde(default)]
+            point_cloud_frame: apps::PointCloudFrame,
+            #[serde(default)]
+            depth_camera: apps::SimDepthCamera,
+            #[serde(default)]
+            imu_axis_remaps: fxhash::FxHashMap<String, localization::ImuAxisRemap>
         }
     }
 }
@@ -78,10 +101,21 @@ fn main() {
         Commands::Sim {
             lunabase_address,
             max_pong_delay_ms,
+            min_steering_interval_ms,
+            dry_run,
+            point_cloud_frame,
+            depth_camera,
+            imu_axis_remaps,
         } => {
             apps::LunasimbotApp {
                 lunabase_address,
                 max_pong_delay_ms: max_pong_delay_ms.unwrap_or_else(default_max_pong_delay_ms),
+                min_steering_interval_ms: min_steering_interval_ms
+                    .unwrap_or_else(default_min_steering_interval_ms),
+                dry_run,
+                point_cloud_frame,
+                depth_camera,
+                imu_axis_remaps,
             }
             .run();
         }
@@ -93,24 +127,32 @@ fn main() {
         Commands::Main {
             lunabase_address,
             max_pong_delay_ms,
+            min_steering_interval_ms,
             lunabase_streaming_address,
             lunabase_audio_streaming_address,
             cameras,
             depth_cameras,
             apriltags,
+            apriltag_workers,
             robot_layout,
+            extrinsic_calibration,
         } => {
             apps::LunabotApp {
                 lunabase_address,
                 lunabase_streaming_address,
                 max_pong_delay_ms: max_pong_delay_ms.unwrap_or_else(default_max_pong_delay_ms),
+                min_steering_interval_ms: min_steering_interval_ms
+                    .unwrap_or_else(default_min_steering_interval_ms),
                 #[cfg(feature = "experimental")]
                 lunabase_audio_streaming_address,
                 cameras,
                 depth_cameras,
                 apriltags,
+                apriltag_workers: apriltag_workers
+                    .unwrap_or(std::num::NonZeroUsize::new(2).unwrap()),
                 robot_layout: robot_layout
                     .unwrap_or_else(|| "robot-layout/lunabot.json".to_string()),
+                extrinsic_calibration,
             }
             .run();
             #[cfg(not(feature = "experimental"))]
@@ -123,6 +165,7 @@ fn main() {
             max_pong_delay_ms,
             depth_cameras,
             robot_layout,
+            extrinsic_calibration,
         } => {
             apps::dataviz::DatavizApp {
                 lunabase_address,
@@ -131,6 +174,7 @@ fn main() {
                 depth_cameras,
                 robot_layout: robot_layout
                     .unwrap_or_else(|| "robot-layout/lunabot.json".to_string()),
+                extrinsic_calibration,
             }
             .run();
         }
@@ -1,11 +1,19 @@
-use nalgebra::{Point3, Transform3, Vector2};
-use pathfinding::{grid::Grid, prelude::astar};
+use std::ops::Range;
+
+use common::units::Meters;
+use nalgebra::{Point2, Point3, Transform3, Vector2};
+use pathfinding::{
+    grid::Grid,
+    prelude::{astar, bfs},
+};
 use tasker::shared::SharedDataReceiver;
 use tracing::{error, warn};
 
-use crate::pipelines::thalassic::{set_observe_depth, ThalassicData};
+use crate::pipelines::thalassic::{with_observe_depth, ThalassicData};
 
 const REACH: usize = 10;
+/// The radius obstacles are expanded by before planning a path.
+const PATHFINDING_ROBOT_RADIUS: Meters<f32> = Meters(0.5);
 
 pub struct DefaultPathfinder {
     pub world_to_grid: Transform3<f64>,
@@ -14,26 +22,40 @@ pub struct DefaultPathfinder {
 }
 
 impl DefaultPathfinder {
+    /// Calculates a path from `from` to `to` into `into`.
+    ///
+    /// If the robot is not in a free cell, the path starts with a way out to the closest free
+    /// cell, and if `to` cannot be reached, the path ends with `to` after the closest reachable
+    /// cell. Returns the range of `into` in between, which was planned through free cells.
     pub fn pathfind(
         &self,
         shared_thalassic_data: &SharedDataReceiver<ThalassicData>,
         from: Point3<f64>,
         to: Point3<f64>,
         into: &mut Vec<Point3<f64>>,
-    ) {
+    ) -> Range<usize> {
         shared_thalassic_data.try_get();
-        set_observe_depth(true);
-        let mut data = shared_thalassic_data.get();
-        loop {
-            if data.current_robot_radius == 0.5 {
-                break;
+        let data = with_observe_depth(|| {
+            let mut data = shared_thalassic_data.get();
+            loop {
+                if data.current_robot_radius == PATHFINDING_ROBOT_RADIUS {
+                    break data;
+                }
+                data.set_robot_radius(PATHFINDING_ROBOT_RADIUS);
+                drop(data);
+                data = shared_thalassic_data.get();
             }
-            data.set_robot_radius(0.5);
-            drop(data);
-            data = shared_thalassic_data.get();
-        }
-        set_observe_depth(false);
+        });
+        self.plan(&data, from, to, into)
+    }
 
+    fn plan(
+        &self,
+        data: &ThalassicData,
+        from: Point3<f64>,
+        to: Point3<f64>,
+        into: &mut Vec<Point3<f64>>,
+    ) -> Range<usize> {
         macro_rules! neighbours {
             ($p: ident) => {
                 self.grid
@@ -105,7 +127,7 @@ impl DefaultPathfinder {
                     }));
                 } else {
                     error!("Failed to find path to safety");
-                    return;
+                    return 0..0;
                 }
             }
         }
@@ -133,14 +155,275 @@ impl DefaultPathfinder {
             astar(&start, |&p| neighbours!(p), heuristic, |p| p == &closest).unwrap()
         })
         .0;
+        let path = shortcut_path(&path, |(x, y)| {
+            let index = y * 128 + x;
+            data.heightmap[index] != 0.0 && !data.expanded_obstacle_map[index].occupied()
+        });
+        let planned_start = into.len();
         into.extend(path.into_iter().map(|(x, y)| {
             let mut p = Point3::new(x as f64, 0.0, y as f64);
             p = self.grid_to_world * p;
             p.y = data.heightmap[y * 128 + x] as f64;
             p
         }));
+        let planned = planned_start..into.len();
         if using_closest {
             into.push(to);
         }
+        planned
+    }
+
+    /// Checks if the part of the path ahead of the robot at `from` only passes through free
+    /// cells, using the latest map.
+    ///
+    /// `path` should only be the part of a path that was planned through free cells, as returned
+    /// by [`Self::pathfind`]. Returns true if no map has been shared yet, as there is nothing to
+    /// check against.
+    pub fn is_path_clear(
+        &self,
+        shared_thalassic_data: &SharedDataReceiver<ThalassicData>,
+        from: Point3<f64>,
+        path: &[Point3<f64>],
+    ) -> bool {
+        let Some(data) = shared_thalassic_data.try_get() else {
+            return true;
+        };
+        self.is_path_clear_in(&data, from, path)
+    }
+
+    fn is_path_clear_in(
+        &self,
+        data: &ThalassicData,
+        from: Point3<f64>,
+        path: &[Point3<f64>],
+    ) -> bool {
+        let to_grid = |p: Point3<f64>| {
+            let p = self.world_to_grid * p;
+            Point2::new(p.x, p.z)
+        };
+        let path: Vec<_> = path.iter().map(|&p| to_grid(p)).collect();
+        path_is_free(&path_ahead(&path, to_grid(from)), |(x, y)| {
+            let index = y * 128 + x;
+            x < 128
+                && index < data.heightmap.len()
+                && data.heightmap[index] != 0.0
+                && !data.expanded_obstacle_map[index].occupied()
+        })
+    }
+}
+
+/// Returns the cells of the path that is left to follow from `from`, starting from the closest
+/// point on the path.
+fn path_ahead(path: &[Point2<f64>], from: Point2<f64>) -> Vec<(usize, usize)> {
+    let to_cell = |p: Point2<f64>| (p.x as usize, p.y as usize);
+    let Some(closest) = path
+        .windows(2)
+        .enumerate()
+        .map(|(i, segment)| {
+            let along = segment[1] - segment[0];
+            let t = if along.magnitude_squared() == 0.0 {
+                0.0
+            } else {
+                ((from - segment[0]).dot(&along) / along.magnitude_squared()).clamp(0.0, 1.0)
+            };
+            (i, segment[0] + along * t)
+        })
+        .min_by(|(_, a), (_, b)| (a - from).magnitude().total_cmp(&(b - from).magnitude()))
+    else {
+        return path.iter().copied().map(to_cell).collect();
+    };
+    let (i, nearest) = closest;
+    std::iter::once(nearest)
+        .chain(path[i + 1..].iter().copied())
+        .map(to_cell)
+        .collect()
+}
+
+/// Returns true if every segment of the path only passes through free cells.
+///
+/// A segment is free if either the straight line between its ends is free, or its end can be
+/// reached through free cells within [`REACH`] cells of its start, which is how the segments of
+/// a path are planned.
+fn path_is_free(path: &[(usize, usize)], is_free: impl Fn((usize, usize)) -> bool) -> bool {
+    path.windows(2).all(|segment| {
+        line_is_free(segment[0], segment[1], &is_free)
+            || reach_is_free(segment[0], segment[1], &is_free)
+    })
+}
+
+/// Returns true if `to` can be reached from `from` by only passing through free cells within
+/// [`REACH`] cells of `from`.
+fn reach_is_free(
+    from: (usize, usize),
+    to: (usize, usize),
+    is_free: &impl Fn((usize, usize)) -> bool,
+) -> bool {
+    if from.0.abs_diff(to.0) > REACH || from.1.abs_diff(to.1) > REACH {
+        return false;
+    }
+    bfs(
+        &from,
+        |&(x, y)| {
+            let mut neighbours = vec![];
+            for nx in x.saturating_sub(1)..=x + 1 {
+                for ny in y.saturating_sub(1)..=y + 1 {
+                    let cell = (nx, ny);
+                    if cell != (x, y)
+                        && nx.abs_diff(from.0) <= REACH
+                        && ny.abs_diff(from.1) <= REACH
+                        && is_free(cell)
+                    {
+                        neighbours.push(cell);
+                    }
+                }
+            }
+            neighbours
+        },
+        |&cell| cell == to,
+    )
+    .is_some()
+}
+
+/// Returns true if every cell that the straight line between the two cells passes through is free.
+fn line_is_free(
+    from: (usize, usize),
+    to: (usize, usize),
+    is_free: &impl Fn((usize, usize)) -> bool,
+) -> bool {
+    let steps = from.0.abs_diff(to.0).max(from.1.abs_diff(to.1));
+    (0..=steps).all(|i| {
+        let t = if steps == 0 {
+            0.0
+        } else {
+            i as f64 / steps as f64
+        };
+        let x = from.0 as f64 + (to.0 as f64 - from.0 as f64) * t;
+        let y = from.1 as f64 + (to.1 as f64 - from.1 as f64) * t;
+        is_free((x.round() as usize, y.round() as usize))
+    })
+}
+
+/// Removes waypoints that can be skipped by driving in a straight line through free cells.
+///
+/// The first and last waypoints are always kept, and every segment of the returned path is
+/// either free or was already in the given path.
+fn shortcut_path(
+    path: &[(usize, usize)],
+    is_free: impl Fn((usize, usize)) -> bool,
+) -> Vec<(usize, usize)> {
+    let Some(&first) = path.first() else {
+        return vec![];
+    };
+    let mut shortcut = vec![first];
+    let mut i = 0;
+    while i + 1 < path.len() {
+        let next = (i + 2..path.len())
+            .rev()
+            .find(|&j| line_is_free(path[i], path[j], &is_free))
+            .unwrap_or(i + 1);
+        shortcut.push(path[next]);
+        i = next;
+    }
+    shortcut
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Transform3};
+    use pathfinding::grid::Grid;
+    use thalassic::Occupancy;
+
+    use crate::pipelines::thalassic::ThalassicData;
+
+    use super::{line_is_free, path_is_free, shortcut_path, DefaultPathfinder};
+
+    #[test]
+    fn zig_zag_around_corner_is_shortened() {
+        // A wall along x = 2 for y <= 3, which the path has to go around
+        let is_free = |(x, y): (usize, usize)| !(x == 2 && y <= 3);
+        let path = [
+            (0, 0),
+            (1, 1),
+            (0, 2),
+            (1, 3),
+            (1, 4),
+            (2, 5),
+            (3, 4),
+            (4, 3),
+            (3, 2),
+            (4, 1),
+            (4, 0),
+        ];
+
+        let shortcut = shortcut_path(&path, is_free);
+        assert_eq!(shortcut.first(), path.first());
+        assert_eq!(shortcut.last(), path.last());
+        assert!(shortcut.len() < path.len(), "{shortcut:?}");
+        for segment in shortcut.windows(2) {
+            assert!(
+                line_is_free(segment[0], segment[1], &is_free),
+                "{segment:?} crosses the wall"
+            );
+        }
+    }
+
+    #[test]
+    fn new_obstacle_invalidates_path() {
+        let path = [(0, 0), (4, 4), (4, 8)];
+        assert!(path_is_free(&path, |_| true));
+
+        // An obstacle appears across the second segment
+        assert!(!path_is_free(&path, |(_, y)| y != 6));
+        // An obstacle next to the path does not matter
+        assert!(path_is_free(&path, |cell| cell != (5, 6)));
+    }
+
+    #[test]
+    fn planned_path_is_clear() {
+        let mut pathfinder = DefaultPathfinder {
+            world_to_grid: Transform3::identity(),
+            grid_to_world: Transform3::identity(),
+            grid: Grid::new(128, 256),
+        };
+        pathfinder.grid.enable_diagonal_mode();
+        pathfinder.grid.fill();
+
+        // Only a 40 by 20 area has been observed, with a wall across it that has a gap at y < 3
+        let mut data = Box::<ThalassicData>::default();
+        for y in 0..20 {
+            for x in 0..40 {
+                data.heightmap[y * 128 + x] = 1.0;
+                if x == 20 && y >= 3 {
+                    data.expanded_obstacle_map[y * 128 + x] = Occupancy::OCCUPIED;
+                }
+            }
+        }
+        // The robot starts inside an obstacle, and the goal has not been observed
+        data.expanded_obstacle_map[10 * 128 + 5] = Occupancy::OCCUPIED;
+        let from = Point3::new(5.0, 0.0, 10.0);
+        let to = Point3::new(60.0, 0.0, 10.0);
+
+        let mut path = vec![];
+        let planned = pathfinder.plan(&data, from, to, &mut path);
+        assert!(planned.start > 0, "{path:?} does not start with a way out");
+        assert_eq!(
+            planned.end,
+            path.len() - 1,
+            "{path:?} does not end with the goal"
+        );
+        assert!(pathfinder.is_path_clear_in(&data, from, &path[planned.clone()]));
+
+        // An obstacle behind the robot does not matter
+        let planned_path = &path[planned];
+        let passed = planned_path[0].z as usize * 128 + planned_path[0].x as usize;
+        data.expanded_obstacle_map[passed] = Occupancy::OCCUPIED;
+        assert!(pathfinder.is_path_clear_in(&data, planned_path[1], planned_path));
+        data.expanded_obstacle_map[passed] = Occupancy::FREE;
+
+        // Closing the gap blocks the path
+        for y in 0..3 {
+            data.expanded_obstacle_map[y * 128 + 20] = Occupancy::OCCUPIED;
+        }
+        assert!(!pathfinder.is_path_clear_in(&data, from, planned_path));
     }
 }
@@ -1,13 +1,14 @@
 use std::{
-    num::NonZeroU32,
+    collections::VecDeque,
+    num::{NonZeroU32, NonZeroUsize},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
 };
 
 use arc_swap::ArcSwapOption;
-use common::THALASSIC_CELL_COUNT;
+use common::{units::Meters, THALASSIC_CELL_COUNT};
 use crossbeam::{
     atomic::AtomicCell,
     sync::{Parker, Unparker},
@@ -15,7 +16,8 @@ use crossbeam::{
 use gputter::is_gputter_initialized;
 use nalgebra::Vector2;
 use tasker::shared::OwnedData;
-use thalassic::{Occupancy, PointCloudStorage, ThalassicBuilder};
+use thalassic::{DepthProjectorBuilder, Occupancy, PointCloudStorage, ThalassicBuilder};
+use tracing::warn;
 
 static OBSERVE_DEPTH: AtomicBool = AtomicBool::new(false);
 static DEPTH_UNPARKER: ArcSwapOption<Unparker> = ArcSwapOption::const_empty();
@@ -33,12 +35,31 @@ pub fn get_observe_depth() -> bool {
     OBSERVE_DEPTH.load(Ordering::Acquire)
 }
 
+/// Parks until `observe` is set, ignoring wake ups left over from earlier resumes.
+fn wait_for_observe(observe: &AtomicBool, parker: &Parker) {
+    while !observe.load(Ordering::Acquire) {
+        parker.park();
+    }
+}
+
+/// Observes depth while `f` runs, then restores whether depth was being observed before.
+///
+/// Depth cameras keep draining frames while depth is not observed, so the first
+/// projections after resuming are of fresh frames.
+pub fn with_observe_depth<T>(f: impl FnOnce() -> T) -> T {
+    let observing = get_observe_depth();
+    set_observe_depth(true);
+    let result = f();
+    set_observe_depth(observing);
+    result
+}
+
 pub struct ThalassicData {
     pub heightmap: [f32; THALASSIC_CELL_COUNT as usize],
     pub gradmap: [f32; THALASSIC_CELL_COUNT as usize],
     pub expanded_obstacle_map: [Occupancy; THALASSIC_CELL_COUNT as usize],
-    pub current_robot_radius: f32,
-    new_robot_radius: AtomicCell<Option<f32>>,
+    pub current_robot_radius: Meters<f32>,
+    new_robot_radius: AtomicCell<Option<Meters<f32>>>,
 }
 
 impl Default for ThalassicData {
@@ -48,39 +69,110 @@ impl Default for ThalassicData {
             gradmap: [0.0; THALASSIC_CELL_COUNT as usize],
             expanded_obstacle_map: [Occupancy::FREE; THALASSIC_CELL_COUNT as usize],
             new_robot_radius: AtomicCell::new(None),
-            current_robot_radius: 0.25,
+            current_robot_radius: Meters(0.25),
         }
     }
 }
 
 impl ThalassicData {
-    pub fn set_robot_radius(&self, radius: f32) {
+    pub fn set_robot_radius(&self, radius: Meters<f32>) {
         self.new_robot_radius.store(Some(radius));
     }
 }
 
+/// Frames that are waiting to be consumed, along with the frames that are free to be reused.
+struct FrameQueue<T> {
+    projected: VecDeque<T>,
+    finished: Vec<T>,
+    max_in_flight: NonZeroUsize,
+    dropped: usize,
+}
+
+impl<T> FrameQueue<T> {
+    fn new(finished: Vec<T>, max_in_flight: NonZeroUsize) -> Self {
+        Self {
+            projected: VecDeque::with_capacity(max_in_flight.get() + 1),
+            finished,
+            max_in_flight,
+            dropped: 0,
+        }
+    }
+
+    /// Queues a projected frame, dropping the oldest queued frame if there are more than
+    /// `max_in_flight` waiting. The dropped frame is free to be reused.
+    ///
+    /// Returns `true` if a frame was dropped.
+    fn push_projected(&mut self, projected: T) -> bool {
+        self.projected.push_back(projected);
+        if self.projected.len() <= self.max_in_flight.get() {
+            return false;
+        }
+        let oldest = self.projected.pop_front().unwrap();
+        self.finished.push(oldest);
+        self.dropped += 1;
+        true
+    }
+
+    /// Takes the newest projected frame. The older queued frames are stale by now, so they are
+    /// dropped and free to be reused.
+    fn take_newest(&mut self) -> Option<T> {
+        let newest = self.projected.pop_back()?;
+        self.dropped += self.projected.len();
+        self.finished.extend(self.projected.drain(..));
+        Some(newest)
+    }
+}
+
+/// Passes the point clouds projected by a depth camera to the thalassic pipeline, and
+/// passes them back once the pipeline is done with them.
 pub struct PointsStorageChannel {
-    projected: AtomicCell<Option<PointCloudStorage>>,
-    finished: AtomicCell<Option<PointCloudStorage>>,
+    frames: Mutex<FrameQueue<PointCloudStorage>>,
     image_size: Vector2<NonZeroU32>,
 }
 
 impl PointsStorageChannel {
-    pub fn new_for(storage: &PointCloudStorage) -> Self {
+    /// Creates a channel where at most `max_in_flight` projected point clouds can wait for
+    /// the pipeline. If the pipeline falls behind, the oldest point clouds are dropped so that
+    /// the camera does not have to wait, and the pipeline only consumes the newest.
+    pub fn new(builder: DepthProjectorBuilder, max_in_flight: NonZeroUsize) -> Self {
+        // One more storage than can be in flight, so that there is always one to project into
+        let storages: Vec<_> = (0..=max_in_flight.get())
+            .map(|_| builder.make_points_storage())
+            .collect();
         Self {
-            projected: AtomicCell::new(None),
-            image_size: storage.get_image_size(),
-            finished: AtomicCell::new(None),
+            image_size: storages[0].get_image_size(),
+            frames: Mutex::new(FrameQueue::new(storages, max_in_flight)),
         }
     }
 
     pub fn set_projected(&self, projected: PointCloudStorage) {
-        self.projected.store(Some(projected));
+        let mut frames = self.frames.lock().unwrap();
+        if frames.push_projected(projected) {
+            warn_dropped(frames.dropped);
+        }
     }
 
     pub fn get_finished(&self) -> Option<PointCloudStorage> {
-        self.finished.take()
+        self.frames.lock().unwrap().finished.pop()
+    }
+
+    fn take_projected(&self) -> Option<PointCloudStorage> {
+        let mut frames = self.frames.lock().unwrap();
+        let dropped = frames.dropped;
+        let newest = frames.take_newest();
+        if frames.dropped > dropped {
+            warn_dropped(frames.dropped);
+        }
+        newest
     }
+
+    fn set_finished(&self, finished: PointCloudStorage) {
+        self.frames.lock().unwrap().finished.push(finished);
+    }
+}
+
+fn warn_dropped(total: usize) {
+    warn!("Dropped point cloud as the thalassic pipeline is not keeping up ({total} total)");
 }
 
 pub fn spawn_thalassic_pipeline(
@@ -118,13 +210,11 @@ pub fn spawn_thalassic_pipeline(
         DEPTH_UNPARKER.store(Some(parker.unparker().clone().into()));
 
         std::thread::spawn(move || loop {
-            if !get_observe_depth() {
-                parker.park();
-            }
+            wait_for_observe(&OBSERVE_DEPTH, &parker);
             let mut points_vec = vec![];
 
             for channel in &point_cloud_channels {
-                let Some(points) = channel.projected.take() else {
+                let Some(points) = channel.take_projected() else {
                     continue;
                 };
                 points_vec.push((channel, points));
@@ -142,13 +232,13 @@ pub fn spawn_thalassic_pipeline(
 
                 if let Some(radius) = new_robot_radius.take() {
                     *current_robot_radius = radius;
-                    pipeline.set_radius(radius);
+                    pipeline.set_radius(radius.0);
                 }
 
                 for (channel, mut points) in points_vec {
                     points =
                         pipeline.provide_points(points, heightmap, gradmap, expanded_obstacle_map);
-                    channel.finished.store(Some(points));
+                    channel.set_finished(points);
                 }
 
                 buffer = owned.pessimistic_share();
@@ -156,3 +246,109 @@ pub fn spawn_thalassic_pipeline(
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc::{channel, RecvTimeoutError},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use crossbeam::sync::Parker;
+
+    use super::{
+        get_observe_depth, set_observe_depth, wait_for_observe, with_observe_depth, FrameQueue,
+    };
+
+    #[test]
+    fn toggle_observe_depth() {
+        set_observe_depth(false);
+        assert!(with_observe_depth(get_observe_depth));
+        assert!(!get_observe_depth());
+
+        set_observe_depth(true);
+        assert!(with_observe_depth(get_observe_depth));
+        assert!(get_observe_depth());
+
+        set_observe_depth(false);
+        assert!(!get_observe_depth());
+    }
+
+    #[test]
+    fn projection_pauses_and_resumes() {
+        let observe = Arc::new(AtomicBool::new(false));
+        let frames = Arc::new(Mutex::new(FrameQueue::new(
+            vec![0; 3],
+            NonZeroUsize::new(2).unwrap(),
+        )));
+        let parker = Parker::new();
+        let unparker = parker.unparker().clone();
+        // A wake up left over from an earlier resume
+        unparker.unpark();
+
+        let (projected_tx, projected_rx) = channel();
+        std::thread::spawn({
+            let observe = observe.clone();
+            let frames = frames.clone();
+            move || loop {
+                wait_for_observe(&observe, &parker);
+                let frame = frames.lock().unwrap().take_newest();
+                if let Some(frame) = frame {
+                    if projected_tx.send(frame).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        // The camera keeps draining frames while paused
+        for frame_number in 1..=3 {
+            let mut frames = frames.lock().unwrap();
+            frames.finished.pop().unwrap();
+            frames.push_projected(frame_number);
+        }
+        assert_eq!(
+            projected_rx.recv_timeout(Duration::from_millis(200)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert_eq!(frames.lock().unwrap().projected, [2, 3]);
+
+        observe.store(true, Ordering::Release);
+        unparker.unpark();
+        assert_eq!(projected_rx.recv_timeout(Duration::from_secs(5)), Ok(3));
+
+        // Leaves the thread parked instead of spinning for the rest of the tests
+        observe.store(false, Ordering::Release);
+    }
+
+    #[test]
+    fn frame_queue_drops_oldest() {
+        let mut frames = FrameQueue::new(vec![0; 3], NonZeroUsize::new(2).unwrap());
+
+        // The camera keeps projecting while the pipeline is stalled
+        for frame_number in 1..=10 {
+            frames.finished.pop().unwrap();
+            frames.push_projected(frame_number);
+            assert!(frames.projected.len() <= 2);
+            assert_eq!(frames.projected.len() + frames.finished.len(), 3);
+        }
+        assert_eq!(frames.dropped, 8);
+
+        // Only the newest frames are left, and the pipeline takes the newest of them
+        assert_eq!(frames.projected, [9, 10]);
+        assert_eq!(frames.take_newest(), Some(10));
+        assert_eq!(frames.dropped, 9);
+        assert!(frames.projected.is_empty());
+        assert_eq!(frames.finished.len(), 2);
+        assert_eq!(frames.take_newest(), None);
+
+        frames.finished.pop().unwrap();
+        assert!(!frames.push_projected(11));
+        assert_eq!(frames.dropped, 9);
+    }
+}
@@ -0,0 +1,212 @@
+use std::{fmt::Write, io::Read};
+
+use anyhow::Context;
+use simple_motion::{ChainBuilder, NodeSerde, StaticNode};
+
+/// How a joint is allowed to translate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranslationKind {
+    Fixed,
+    Linear,
+    Free,
+}
+
+/// How a joint is allowed to rotate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationKind {
+    Fixed,
+    OneAxis,
+    Free,
+}
+
+/// The kind of joint a link is expected to have. `None` accepts any kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectedJoint {
+    pub translation: Option<TranslationKind>,
+    pub rotation: Option<RotationKind>,
+}
+
+impl ExpectedJoint {
+    pub const ANY: Self = Self {
+        translation: None,
+        rotation: None,
+    };
+    pub const FIXED: Self = Self {
+        translation: Some(TranslationKind::Fixed),
+        rotation: Some(RotationKind::Fixed),
+    };
+    pub const FREE: Self = Self {
+        translation: Some(TranslationKind::Free),
+        rotation: Some(RotationKind::Free),
+    };
+}
+
+/// The links and joints that the code depends on.
+#[derive(Debug, Clone)]
+pub struct ExpectedLayout<'a> {
+    pub root: ExpectedJoint,
+    pub links: Vec<(&'a str, ExpectedJoint)>,
+}
+
+fn translation_kind(node: &StaticNode) -> TranslationKind {
+    if node.is_origin_fixed() {
+        TranslationKind::Fixed
+    } else if node.is_origin_linear() {
+        TranslationKind::Linear
+    } else {
+        TranslationKind::Free
+    }
+}
+
+fn rotation_kind(node: &StaticNode) -> RotationKind {
+    if node.is_rotation_fixed() {
+        RotationKind::Fixed
+    } else if node.is_rotation_one_axis() {
+        RotationKind::OneAxis
+    } else {
+        RotationKind::Free
+    }
+}
+
+fn check_joint(node: &StaticNode, name: &str, expected: ExpectedJoint, problems: &mut String) {
+    if let Some(kind) = expected.translation {
+        let actual = translation_kind(node);
+        if actual != kind {
+            let _ = write!(
+                problems,
+                "\n  {name} should have a {kind:?} translation but it is {actual:?}"
+            );
+        }
+    }
+    if let Some(kind) = expected.rotation {
+        let actual = rotation_kind(node);
+        if actual != kind {
+            let _ = write!(
+                problems,
+                "\n  {name} should have a {kind:?} rotation but it is {actual:?}"
+            );
+        }
+    }
+}
+
+/// Checks that every link the code depends on exists with the expected kind of joint.
+///
+/// All problems are listed in the returned error, so that they can be fixed at once.
+pub fn validate_robot_chain(chain: &StaticNode, expected: &ExpectedLayout) -> anyhow::Result<()> {
+    let mut problems = String::new();
+    check_joint(&chain.get_root(), "The root", expected.root, &mut problems);
+    for &(name, joint) in &expected.links {
+        match chain.get_node_with_name(name) {
+            Some(node) => check_joint(&node, name, joint, &mut problems),
+            None => {
+                let _ = write!(problems, "\n  {name} is missing");
+            }
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Robot chain does not match the expected layout:{problems}"
+        ))
+    }
+}
+
+/// A robot chain along with the file it was loaded from, so that errors can say
+/// which file needs fixing.
+pub struct RobotLayout {
+    pub chain: StaticNode,
+    path: String,
+}
+
+impl RobotLayout {
+    pub fn load(path: impl Into<String>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let file = std::fs::File::open(&path)
+            .with_context(|| format!("Failed to read robot layout {path}"))?;
+        Self::from_reader(file, path)
+    }
+
+    pub fn from_reader(reader: impl Read, path: impl Into<String>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let node = NodeSerde::from_reader(reader)
+            .with_context(|| format!("Failed to parse robot layout {path}"))?;
+        Ok(Self {
+            chain: ChainBuilder::from(node).finish_static(),
+            path,
+        })
+    }
+
+    pub fn validate(&self, expected: &ExpectedLayout) -> anyhow::Result<()> {
+        validate_robot_chain(&self.chain, expected)
+            .with_context(|| format!("Robot layout {} is invalid", self.path))
+    }
+
+    /// Finds the link with the given name, returning an error naming the link and the robot
+    /// layout if it is missing.
+    pub fn find_link(&self, name: &str) -> anyhow::Result<StaticNode> {
+        self.chain
+            .get_node_with_name(name)
+            .with_context(|| format!("Link {name:?} is missing from robot layout {}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_robot_chain, ExpectedJoint, ExpectedLayout, RobotLayout};
+
+    const LAYOUT: &str = r#"{
+        "free_origin": [0.0, 0.0, 0.0],
+        "free_euler": [0.0, 0.0, 0.0],
+        "children": [
+            {
+                "name": "front_camera",
+                "origin": [0.0, 0.5, -0.3]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn missing_link_is_an_error() {
+        let layout = RobotLayout::from_reader(LAYOUT.as_bytes(), "test.json").unwrap();
+        assert!(layout.find_link("front_camera").is_ok());
+
+        let Err(e) = layout.find_link("depth_camera") else {
+            panic!("Found a link that is not in the layout");
+        };
+        let e = e.to_string();
+        assert!(e.contains("\"depth_camera\""), "{e}");
+        assert!(e.contains("test.json"), "{e}");
+    }
+
+    #[test]
+    fn invalid_layout_is_an_error() {
+        let e = match RobotLayout::from_reader("{".as_bytes(), "broken.json") {
+            Ok(_) => panic!("Parsed an invalid robot layout"),
+            Err(e) => e.to_string(),
+        };
+        assert!(e.contains("broken.json"), "{e}");
+    }
+
+    #[test]
+    fn mismatched_layout_is_an_error() {
+        let layout = RobotLayout::from_reader(LAYOUT.as_bytes(), "test.json").unwrap();
+        let mut expected = ExpectedLayout {
+            root: ExpectedJoint::FREE,
+            links: vec![("front_camera", ExpectedJoint::FIXED)],
+        };
+        validate_robot_chain(&layout.chain, &expected).unwrap();
+
+        expected.root = ExpectedJoint::FIXED;
+        expected.links.push(("depth_camera", ExpectedJoint::ANY));
+        let e = format!("{:#}", layout.validate(&expected).unwrap_err());
+        assert!(e.contains("test.json"), "{e}");
+        assert!(
+            e.contains("The root should have a Fixed translation"),
+            "{e}"
+        );
+        assert!(e.contains("The root should have a Fixed rotation"), "{e}");
+        assert!(e.contains("depth_camera is missing"), "{e}");
+        assert!(!e.contains("front_camera"), "{e}");
+    }
+}
@@ -5,17 +5,22 @@ use std::{
     time::{Duration, Instant},
 };
 
-use cakap2::{packet::Action, Event, PeerStateMachine, RecommendedAction};
-use common::{FromLunabot, LunabotStage};
+use cakap2::{error::BuildPacketError, packet::Action, Event, PeerStateMachine, RecommendedAction};
+use common::{sequence::SequenceCounter, FromLunabot, LunabotStage, PowerStatus, Reliability};
 use crossbeam::atomic::AtomicCell;
 use tasker::get_tokio_handle;
 use tasker::tokio::{self, net::UdpSocket, sync::mpsc};
-use tracing::{error, warn};
+use tracing::{error, info, warn};
+
+/// How often the latest power reading is sent to the lunabase.
+const POWER_STATUS_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Clone)]
 pub struct PacketBuilder {
     builder: cakap2::packet::PacketBuilder,
     packet_tx: mpsc::UnboundedSender<Action>,
+    address_tx: mpsc::UnboundedSender<SocketAddr>,
+    power_status: Arc<AtomicCell<Option<PowerStatus>>>,
 }
 
 impl Deref for PacketBuilder {
@@ -30,6 +35,57 @@ impl PacketBuilder {
     pub fn send_packet(&self, packet: Action) {
         let _ = self.packet_tx.send(packet);
     }
+
+    /// Sends all future packets to the lunabase at the given address, such as after the
+    /// lunabase has moved to a different interface.
+    ///
+    /// The connection is re-established with a reconnection message to the new address.
+    pub fn set_lunabase_address(&self, address: SocketAddr) {
+        let _ = self.address_tx.send(address);
+    }
+
+    /// Provides the latest reading of the battery, which is sent to the lunabase every
+    /// [`POWER_STATUS_INTERVAL`].
+    // Nothing measures the battery yet
+    #[allow(dead_code)]
+    pub fn set_power_status(&self, status: PowerStatus) {
+        self.power_status.store(Some(status));
+    }
+}
+
+/// Samples the latest power reading at a fixed cadence.
+struct PowerStatusProducer {
+    power_status: Arc<AtomicCell<Option<PowerStatus>>>,
+    next_at: Instant,
+}
+
+impl PowerStatusProducer {
+    /// Returns the message to send if it is time to send one and there is a reading to send.
+    fn poll(&mut self, now: Instant) -> Option<FromLunabot> {
+        if now < self.next_at {
+            return None;
+        }
+        self.next_at = now + POWER_STATUS_INTERVAL;
+        self.power_status.load().map(FromLunabot::PowerStatus)
+    }
+}
+
+/// Encodes a sequenced message into a packet that is sent reliably or not according to the
+/// [`Reliability`] of the message.
+fn encode_packet(
+    builder: &cakap2::packet::PacketBuilder,
+    bitcode_buffer: &mut bitcode::Buffer,
+    sequence: &mut SequenceCounter,
+    msg: &FromLunabot,
+) -> Result<Action, BuildPacketError> {
+    let body = bitcode_buffer
+        .encode(&sequence.wrap(msg.clone()))
+        .to_vec()
+        .into();
+    Ok(match msg.reliability() {
+        Reliability::Reliable => Action::SendReliable(builder.new_reliable(body)?),
+        Reliability::Unreliable => Action::SendUnreliable(builder.new_unreliable(body)?),
+    })
 }
 
 pub struct LunabaseConn<F> {
@@ -47,6 +103,12 @@ impl<F: FnMut(&[u8]) -> bool + Send + 'static> LunabaseConn<F> {
         let mut cakap_sm = PeerStateMachine::new(Duration::from_millis(150), 1024, 1400);
         let packet_builder = cakap_sm.get_packet_builder();
         let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        let (address_tx, mut address_rx) = mpsc::unbounded_channel();
+        let power_status = Arc::new(AtomicCell::new(None));
+        let mut power_status_producer = PowerStatusProducer {
+            power_status: power_status.clone(),
+            next_at: Instant::now(),
+        };
 
         get_tokio_handle().spawn(async move {
             let udp = loop {
@@ -66,24 +128,22 @@ impl<F: FnMut(&[u8]) -> bool + Send + 'static> LunabaseConn<F> {
                 break udp;
             };
 
-            let mut action: RecommendedAction<'_, '_> = cakap_sm.send_reconnection_msg(Instant::now()).0;
+            let (mut action, reconnection_index): (RecommendedAction<'_, '_>, _) = cakap_sm.send_reconnection_msg(Instant::now());
             let mut wait_for: Option<Duration>;
 
+            // Packets that fail to send are dropped instead of retried, as reliable packets
+            // are retransmitted by cakap anyway.
             macro_rules! send {
                 ($data: expr) => {{
-                    loop {
-                        if let Err(e) = udp.send($data).await {
-                            if e.kind() == std::io::ErrorKind::ConnectionRefused {
-                                if self.lunabase_address.ip().is_loopback() {
-                                    continue;
-                                }
-                            }
+                    if let Err(e) = udp.send($data).await {
+                        // The lunabase may not have started yet if it is on the same machine
+                        if e.kind() != std::io::ErrorKind::ConnectionRefused
+                            || !self.lunabase_address.ip().is_loopback()
+                        {
                             error!("Failed to send data to lunabase: {e}");
-                            continue;
                         }
-                        action = cakap_sm.poll(Event::NoEvent, Instant::now());
-                        break;
                     }
+                    action = cakap_sm.poll(Event::NoEvent, Instant::now());
                 }};
             }
 
@@ -120,17 +180,24 @@ impl<F: FnMut(&[u8]) -> bool + Send + 'static> LunabaseConn<F> {
             }
             handle!();
             let mut bitcode_buffer = bitcode::Buffer::new();
+            let mut sequence = SequenceCounter::default();
             let mut ping_at = tokio::time::Instant::now();
 
+            // Every arm either yields the next action or skips to the next iteration, so that the
+            // previous action, which may borrow `buf`, is never held across a `recv`
             loop {
-                tokio::select! {
+                action = tokio::select! {
                     _ = tokio::time::sleep_until(ping_at) => {
-                        let bytes = bitcode_buffer.encode(&FromLunabot::Ping(self.lunabot_stage.load()));
-                        let packet = cakap_sm.get_packet_builder().new_unreliable(bytes.to_vec().into()).unwrap();
-                        action = cakap_sm.poll(Event::Action(Action::SendUnreliable(packet)), Instant::now());
-                        handle!();
                         ping_at = tokio::time::Instant::now() + Duration::from_millis(800);
-                        continue;
+                        let packet = encode_packet(&cakap_sm.get_packet_builder(), &mut bitcode_buffer, &mut sequence, &FromLunabot::Ping(self.lunabot_stage.load())).unwrap();
+                        cakap_sm.poll(Event::Action(packet), Instant::now())
+                    }
+                    _ = tokio::time::sleep_until(power_status_producer.next_at.into()) => {
+                        let Some(msg) = power_status_producer.poll(Instant::now()) else {
+                            continue;
+                        };
+                        let packet = encode_packet(&cakap_sm.get_packet_builder(), &mut bitcode_buffer, &mut sequence, &msg).unwrap();
+                        cakap_sm.poll(Event::Action(packet), Instant::now())
                     }
                     _ = async {
                         if let Some(duration) = wait_for {
@@ -139,7 +206,7 @@ impl<F: FnMut(&[u8]) -> bool + Send + 'static> LunabaseConn<F> {
                             std::future::pending::<()>().await;
                         }
                     } => {
-                        action = cakap_sm.poll(Event::NoEvent, Instant::now());
+                        cakap_sm.poll(Event::NoEvent, Instant::now())
                     }
                     packet = async {
                         if let Some(packet) = packet_rx.recv().await {
@@ -149,7 +216,25 @@ impl<F: FnMut(&[u8]) -> bool + Send + 'static> LunabaseConn<F> {
                             std::future::pending().await
                         }
                     } => {
-                        action = cakap_sm.poll(Event::Action(packet), Instant::now());
+                        cakap_sm.poll(Event::Action(packet), Instant::now())
+                    }
+                    address = async {
+                        if let Some(address) = address_rx.recv().await {
+                            address
+                        } else {
+                            std::future::pending().await
+                        }
+                    } => {
+                        if let Err(e) = udp.connect(address).await {
+                            error!("Failed to connect to lunabase at {address}: {e}");
+                            continue;
+                        }
+                        info!("Connected to lunabase at {address}");
+                        self.lunabase_address = address;
+                        // Every reconnection message has the same index, so the previous one has to
+                        // be cancelled in case the old address never acknowledged it
+                        let _ = cakap_sm.poll(Event::Action(Action::CancelReliable(reconnection_index)), Instant::now());
+                        cakap_sm.send_reconnection_msg(Instant::now()).0
                     }
                     result = udp.recv(&mut buf) => {
                         let n = match result {
@@ -166,9 +251,9 @@ impl<F: FnMut(&[u8]) -> bool + Send + 'static> LunabaseConn<F> {
                             }
                         };
                         // println!("{:?}", &buf[..n]);
-                        action = cakap_sm.poll(Event::IncomingData(&buf[..n]), Instant::now());
+                        cakap_sm.poll(Event::IncomingData(&buf[..n]), Instant::now())
                     }
-                }
+                };
                 handle!();
             }
         });
@@ -176,6 +261,130 @@ impl<F: FnMut(&[u8]) -> bool + Send + 'static> LunabaseConn<F> {
         PacketBuilder {
             builder: packet_builder,
             packet_tx,
+            address_tx,
+            power_status,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use cakap2::packet::Action;
+    use common::{sequence::SequenceCounter, FromLunabot, LunabotStage, PowerStatus};
+    use crossbeam::atomic::AtomicCell;
+
+    use super::{encode_packet, LunabaseConn, PowerStatusProducer, POWER_STATUS_INTERVAL};
+
+    /// Returns `true` if a datagram containing `payload` arrives before the socket times out.
+    fn received(socket: &UdpSocket, payload: &[u8]) -> bool {
+        let mut buf = [0u8; 1408];
+        while let Ok(n) = socket.recv(&mut buf) {
+            if buf[..n].windows(payload.len()).any(|w| w == payload) {
+                return true;
+            }
         }
+        false
+    }
+
+    #[test]
+    fn messages_use_their_reliability() {
+        let cakap_sm = cakap2::PeerStateMachine::new(Duration::from_millis(150), 1024, 1400);
+        let mut bitcode_buffer = bitcode::Buffer::new();
+        let mut sequence = SequenceCounter::default();
+        for stage in [LunabotStage::TeleOp, LunabotStage::SoftStop] {
+            let packet = encode_packet(
+                &cakap_sm.get_packet_builder(),
+                &mut bitcode_buffer,
+                &mut sequence,
+                &common::FromLunabot::Ping(stage),
+            )
+            .unwrap();
+            assert!(matches!(packet, Action::SendUnreliable(_)));
+        }
+    }
+
+    #[test]
+    fn power_status_rate() {
+        let power_status = Arc::new(AtomicCell::new(None));
+        let start = Instant::now();
+        let mut producer = PowerStatusProducer {
+            power_status: power_status.clone(),
+            next_at: start,
+        };
+        // Nothing is sent until there is a reading
+        assert_eq!(producer.poll(start), None);
+
+        let status = PowerStatus {
+            voltage: 24.0,
+            current: 3.0,
+            percent: 90,
+        };
+        power_status.store(Some(status));
+        let mut sent = 0;
+        let mut now = start;
+        while now < start + POWER_STATUS_INTERVAL * 5 {
+            now += Duration::from_millis(100);
+            if let Some(msg) = producer.poll(now) {
+                assert_eq!(msg, FromLunabot::PowerStatus(status));
+                let packet = encode_packet(
+                    &cakap2::PeerStateMachine::new(Duration::from_millis(150), 1024, 1400)
+                        .get_packet_builder(),
+                    &mut bitcode::Buffer::new(),
+                    &mut SequenceCounter::default(),
+                    &msg,
+                )
+                .unwrap();
+                assert!(matches!(packet, Action::SendUnreliable(_)));
+                sent += 1;
+            }
+        }
+        assert_eq!(sent, 5);
+    }
+
+    #[test]
+    fn lunabase_address_change() {
+        let bind = || {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            socket
+        };
+        let first = bind();
+        let second = bind();
+        let address = |socket: &UdpSocket| -> SocketAddr { socket.local_addr().unwrap() };
+
+        let packet_builder = LunabaseConn {
+            lunabase_address: address(&first),
+            on_msg: |_: &[u8]| true,
+            lunabot_stage: Arc::new(AtomicCell::new(LunabotStage::SoftStop)),
+        }
+        .connect_to_lunabase();
+        let send = |payload: &[u8]| {
+            let packet = packet_builder
+                .new_unreliable(payload.to_vec().into())
+                .unwrap();
+            packet_builder.send_packet(Action::SendUnreliable(packet));
+        };
+
+        send(b"before");
+        assert!(received(&first, b"before"));
+
+        packet_builder.set_lunabase_address(address(&second));
+        // Wait for the reconnection message so that the change has been applied
+        let mut buf = [0u8; 1408];
+        second.recv(&mut buf).unwrap();
+        // Drain anything that was sent to the old address before the change
+        while first.recv(&mut buf).is_ok() {}
+
+        send(b"after");
+        assert!(received(&second, b"after"));
+        assert!(!received(&first, b"after"));
     }
 }
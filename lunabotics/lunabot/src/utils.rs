@@ -1,6 +1,10 @@
-use std::ops::{Add, Mul, Sub};
+use std::{
+    ops::{Add, Mul, Sub},
+    time::{Duration, Instant},
+};
 
 use nalgebra::{Quaternion, SimdRealField, UnitQuaternion, UnitVector3, Vector3};
+use spin_sleep::SpinSleeper;
 
 pub fn lerp_value(delta: f64, speed: f64) -> f64 {
     0.5f64.powf(speed * delta)
@@ -43,3 +47,88 @@ where
     let swing = src * twist.conjugate();
     (swing, twist)
 }
+
+/// Paces a loop to a fixed period, accounting for how long each iteration takes.
+pub struct FixedRate {
+    period: Duration,
+    next: Option<Instant>,
+    sleeper: SpinSleeper,
+    overruns: usize,
+}
+
+impl FixedRate {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            next: None,
+            sleeper: SpinSleeper::default(),
+            overruns: 0,
+        }
+    }
+
+    /// Sleeps until the next iteration is due.
+    ///
+    /// Returns false if the previous iteration took longer than the period. The loop is not
+    /// sped up afterwards to catch up, so the next iteration is due one period from now.
+    pub fn wait(&mut self) -> bool {
+        let (on_time, sleep) = self.advance(Instant::now());
+        if !sleep.is_zero() {
+            self.sleeper.sleep(sleep);
+        }
+        on_time
+    }
+
+    /// Schedules the next iteration as of `now`, returning whether the previous iteration was
+    /// on time and how long to sleep until the next one is due.
+    fn advance(&mut self, now: Instant) -> (bool, Duration) {
+        let Some(next) = self.next else {
+            self.next = Some(now + self.period);
+            return (true, Duration::ZERO);
+        };
+        if now > next {
+            self.overruns += 1;
+            self.next = Some(now + self.period);
+            return (false, Duration::ZERO);
+        }
+        self.next = Some(next + self.period);
+        (true, next - now)
+    }
+
+    /// How many iterations have taken longer than the period.
+    #[allow(dead_code)]
+    pub fn overruns(&self) -> usize {
+        self.overruns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::FixedRate;
+
+    #[test]
+    fn fixed_rate_matches_target() {
+        let period = Duration::from_millis(10);
+        let work = Duration::from_millis(3);
+        let mut rate = FixedRate::new(period);
+        let start = Instant::now();
+        let mut now = start;
+        assert_eq!(rate.advance(now), (true, Duration::ZERO));
+        for i in 1..=20 {
+            // Work that takes part of the period should not slow the loop down
+            now += work;
+            let (on_time, sleep) = rate.advance(now);
+            assert!(on_time);
+            now += sleep;
+            assert_eq!(now - start, period * i);
+        }
+        assert_eq!(rate.overruns(), 0);
+
+        // The next iteration after an overrun is due one period later
+        now += Duration::from_millis(15);
+        assert_eq!(rate.advance(now), (false, Duration::ZERO));
+        assert_eq!(rate.overruns(), 1);
+        assert_eq!(rate.advance(now + work), (true, period - work));
+    }
+}
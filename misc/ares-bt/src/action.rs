@@ -57,13 +57,13 @@ pub struct AlwaysFail;
 
 impl<B> Behavior<B> for AlwaysFail {
     fn run(&mut self, _blackboard: &mut B) -> Status {
-        Status::Failure
+        Status::FAILURE
     }
 }
 
 impl<B> FallibleBehavior<B> for AlwaysFail {
     fn run_fallible(&mut self, _blackboard: &mut B) -> FallibleStatus {
-        FallibleStatus::Failure
+        FallibleStatus::FAILURE
     }
 }
 
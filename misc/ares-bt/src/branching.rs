@@ -59,7 +59,7 @@ where
                     self.state = IfElseState::IfTrue;
                     self.if_true.run(blackboard)
                 }
-                Status::Failure => {
+                Status::Failure(_) => {
                     self.state = IfElseState::IfFalse;
                     self.if_false.run(blackboard)
                 }
@@ -104,7 +104,7 @@ where
                     self.state = IfElseState::IfTrue;
                     self.if_true.run_infallible(blackboard)
                 }
-                Status::Failure => {
+                Status::Failure(_) => {
                     self.state = IfElseState::IfFalse;
                     self.if_false.run_infallible(blackboard)
                 }
@@ -135,7 +135,7 @@ where
                     self.state = IfElseState::IfTrue;
                     self.if_true.run_fallible(blackboard)
                 }
-                Status::Failure => {
+                Status::Failure(_) => {
                     self.state = IfElseState::IfFalse;
                     self.if_false.run_fallible(blackboard)
                 }
@@ -166,7 +166,7 @@ where
                     self.state = IfElseState::IfTrue;
                     self.if_true.run_eternal(blackboard)
                 }
-                Status::Failure => {
+                Status::Failure(_) => {
                     self.state = IfElseState::IfFalse;
                     self.if_false.run_eternal(blackboard)
                 }
@@ -227,7 +227,7 @@ where
             match self.try_behavior.run(blackboard) {
                 Status::Running => return Status::Running,
                 Status::Success => return Status::Success,
-                Status::Failure => {
+                Status::Failure(_) => {
                     self.trying = false;
                     self.catch.run(blackboard)
                 }
@@ -266,7 +266,7 @@ where
             match self.try_behavior.run(blackboard) {
                 Status::Running => return InfallibleStatus::Running,
                 Status::Success => return InfallibleStatus::Success,
-                Status::Failure => {
+                Status::Failure(_) => {
                     self.trying = false;
                     self.catch.run_infallible(blackboard)
                 }
@@ -292,7 +292,7 @@ where
         let result = if self.trying {
             match self.try_behavior.run_fallible(blackboard) {
                 FallibleStatus::Running => return FallibleStatus::Running,
-                FallibleStatus::Failure => {
+                FallibleStatus::Failure(_) => {
                     self.trying = false;
                     self.catch.run_fallible(blackboard)
                 }
@@ -329,3 +329,105 @@ impl<A, B> TryCatch<A, B> {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// A condition that succeeds if a value read from the blackboard compares favorably
+/// against a threshold, and fails otherwise.
+pub struct Threshold<F, N> {
+    pub get: F,
+    pub comparison: Comparison,
+    pub threshold: N,
+}
+
+impl<F, N> Threshold<F, N> {
+    pub fn new(get: F, comparison: Comparison, threshold: N) -> Self {
+        Self {
+            get,
+            comparison,
+            threshold,
+        }
+    }
+
+    pub fn less_than(get: F, threshold: N) -> Self {
+        Self::new(get, Comparison::Less, threshold)
+    }
+
+    pub fn greater_than(get: F, threshold: N) -> Self {
+        Self::new(get, Comparison::Greater, threshold)
+    }
+}
+
+impl<F, N, D> Behavior<D> for Threshold<F, N>
+where
+    F: FnMut(&mut D) -> N,
+    N: PartialOrd,
+{
+    fn run(&mut self, blackboard: &mut D) -> Status {
+        let value = (self.get)(blackboard);
+        match self.comparison {
+            Comparison::Less => value < self.threshold,
+            Comparison::LessOrEqual => value <= self.threshold,
+            Comparison::Greater => value > self.threshold,
+            Comparison::GreaterOrEqual => value >= self.threshold,
+        }
+        .into()
+    }
+}
+
+impl<F, N> CancelSafe for Threshold<F, N> {
+    fn reset(&mut self) {}
+}
+
+impl<F, N> IntoRon for Threshold<F, N>
+where
+    N: std::fmt::Debug,
+{
+    fn into_ron(&self) -> ron::Value {
+        ron::Value::String(format!("{:?} {:?}", self.comparison, self.threshold))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Behavior, Status};
+
+    use super::{IfElse, Threshold};
+
+    struct Blackboard {
+        battery: f32,
+        returning: bool,
+    }
+
+    #[test]
+    fn threshold_in_if_else() {
+        let mut behavior = IfElse::new(
+            Threshold::greater_than(|blackboard: &mut Blackboard| blackboard.battery, 20.0),
+            |blackboard: &mut Blackboard| {
+                blackboard.returning = false;
+                Status::Success
+            },
+            |blackboard: &mut Blackboard| {
+                blackboard.returning = true;
+                Status::Success
+            },
+        );
+
+        let mut blackboard = Blackboard {
+            battery: 50.0,
+            returning: true,
+        };
+        assert_eq!(behavior.run(&mut blackboard), Status::Success);
+        assert!(!blackboard.returning);
+
+        blackboard.battery = 20.0;
+        assert_eq!(behavior.run(&mut blackboard), Status::Success);
+        assert!(blackboard.returning);
+    }
+}
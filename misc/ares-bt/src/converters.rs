@@ -1,8 +1,11 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    Behavior, CancelSafe, EternalBehavior, EternalStatus, FallibleBehavior, FallibleStatus,
-    InfallibleBehavior, InfallibleStatus, IntoRon, Status,
+    Behavior, CancelSafe, EternalBehavior, EternalStatus, FailureReason, FallibleBehavior,
+    FallibleStatus, InfallibleBehavior, InfallibleStatus, IntoRon, Status,
 };
 
 pub struct InfallibleShim<A>(pub A);
@@ -53,7 +56,7 @@ where
     fn run(&mut self, blackboard: &mut B) -> Status {
         match self.0.run_fallible(blackboard) {
             FallibleStatus::Running => Status::Running,
-            FallibleStatus::Failure => Status::Failure,
+            FallibleStatus::Failure(reason) => Status::Failure(reason),
         }
     }
 }
@@ -124,8 +127,8 @@ where
 {
     fn run(&mut self, blackboard: &mut B) -> Status {
         match self.0.run(blackboard) {
-            Status::Failure => Status::Success,
-            Status::Success => Status::Failure,
+            Status::Failure(_) => Status::Success,
+            Status::Success => Status::FAILURE,
             Status::Running => Status::Running,
         }
     }
@@ -160,7 +163,7 @@ where
     fn run_infallible(&mut self, blackboard: &mut B) -> InfallibleStatus {
         match self.0.run_fallible(blackboard) {
             FallibleStatus::Running => InfallibleStatus::Running,
-            FallibleStatus::Failure => InfallibleStatus::Success,
+            FallibleStatus::Failure(_) => InfallibleStatus::Success,
         }
     }
 }
@@ -172,7 +175,7 @@ where
     fn run_fallible(&mut self, blackboard: &mut B) -> FallibleStatus {
         match self.0.run_infallible(blackboard) {
             InfallibleStatus::Running => FallibleStatus::Running,
-            InfallibleStatus::Success => FallibleStatus::Failure,
+            InfallibleStatus::Success => FallibleStatus::FAILURE,
         }
     }
 }
@@ -195,7 +198,7 @@ where
     fn run(&mut self, blackboard: &mut B) -> Status {
         match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.0.run(blackboard))) {
             Ok(status) => status,
-            Err(_) => Status::Failure,
+            Err(_) => Status::failure("panicked"),
         }
     }
 }
@@ -218,7 +221,7 @@ where
             self.0.run_fallible(blackboard)
         })) {
             Ok(status) => status,
-            Err(_) => FallibleStatus::Failure,
+            Err(_) => FallibleStatus::failure("panicked"),
         }
     }
 }
@@ -239,6 +242,74 @@ where
     }
 }
 
+/// Keeps running the inner behavior until the duration has elapsed since the first tick, then
+/// returns the status of its last run.
+///
+/// The inner behavior is run again every tick, even if it finished before the duration elapsed.
+pub struct Timed<A> {
+    pub inner: A,
+    pub duration: Duration,
+    start_time: Option<Instant>,
+}
+
+impl<A> Timed<A> {
+    pub fn new(inner: A, duration: Duration) -> Self {
+        Self {
+            inner,
+            duration,
+            start_time: None,
+        }
+    }
+}
+
+impl<A, B> Behavior<B> for Timed<A>
+where
+    A: Behavior<B>,
+{
+    fn run(&mut self, blackboard: &mut B) -> Status {
+        let start_time = *self.start_time.get_or_insert_with(Instant::now);
+        let status = self.inner.run(blackboard);
+        if start_time.elapsed() < self.duration {
+            Status::Running
+        } else {
+            self.start_time = None;
+            status
+        }
+    }
+}
+
+impl<A> CancelSafe for Timed<A>
+where
+    A: CancelSafe,
+{
+    fn reset(&mut self) {
+        self.start_time = None;
+        self.inner.reset();
+    }
+}
+
+impl<A> IntoRon for Timed<A>
+where
+    A: IntoRon,
+{
+    fn into_ron(&self) -> ron::Value {
+        ron::Value::Map(
+            [
+                (
+                    ron::Value::String("timed".to_string()),
+                    self.inner.into_ron(),
+                ),
+                (
+                    ron::Value::String("duration".to_string()),
+                    ron::Value::String(format!("{:?}", self.duration)),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
 pub struct Rename<A> {
     pub name: Cow<'static, str>,
     pub behavior: A,
@@ -259,6 +330,252 @@ impl<A> IntoRon for Rename<A> {
     }
 }
 
+/// Runs the inner behavior until it finishes once, then returns the same status forever
+/// without running it again.
+///
+/// Resetting does not clear the result, so the inner behavior never runs twice even if the
+/// tree is re-entered.
+pub struct Once<A> {
+    pub inner: A,
+    pub result: Option<Status>,
+}
+
+impl<A> Once<A> {
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            result: None,
+        }
+    }
+}
+
+impl<A, B> Behavior<B> for Once<A>
+where
+    A: Behavior<B>,
+{
+    fn run(&mut self, blackboard: &mut B) -> Status {
+        if let Some(result) = self.result {
+            return result;
+        }
+        let status = self.inner.run(blackboard);
+        if !status.is_running() {
+            self.result = Some(status);
+        }
+        status
+    }
+}
+
+impl<A> CancelSafe for Once<A>
+where
+    A: CancelSafe,
+{
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+impl<A> IntoRon for Once<A>
+where
+    A: IntoRon,
+{
+    fn into_ron(&self) -> ron::Value {
+        ron::Value::Map(
+            [(
+                ron::Value::String("once".to_string()),
+                self.inner.into_ron(),
+            )]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
+/// Gives a reason to failures of the inner behavior that do not already have one.
+///
+/// The innermost reason is the most specific, so it is never overwritten.
+pub struct WithFailureReason<A> {
+    pub reason: &'static str,
+    pub behavior: A,
+}
+
+impl<A> WithFailureReason<A> {
+    pub fn new(reason: &'static str, behavior: A) -> Self {
+        Self { reason, behavior }
+    }
+}
+
+impl<A, B> Behavior<B> for WithFailureReason<A>
+where
+    A: Behavior<B>,
+{
+    fn run(&mut self, blackboard: &mut B) -> Status {
+        match self.behavior.run(blackboard) {
+            Status::Failure(FailureReason::Unspecified) => Status::failure(self.reason),
+            status => status,
+        }
+    }
+}
+
+impl<A, B> FallibleBehavior<B> for WithFailureReason<A>
+where
+    A: FallibleBehavior<B>,
+{
+    fn run_fallible(&mut self, blackboard: &mut B) -> FallibleStatus {
+        match self.behavior.run_fallible(blackboard) {
+            FallibleStatus::Failure(FailureReason::Unspecified) => {
+                FallibleStatus::failure(self.reason)
+            }
+            status => status,
+        }
+    }
+}
+
+impl<A> CancelSafe for WithFailureReason<A>
+where
+    A: CancelSafe,
+{
+    fn reset(&mut self) {
+        self.behavior.reset();
+    }
+}
+
+impl<A> IntoRon for WithFailureReason<A>
+where
+    A: IntoRon,
+{
+    fn into_ron(&self) -> ron::Value {
+        ron::Value::Map(
+            [
+                (
+                    ron::Value::String("failure_reason".to_string()),
+                    ron::Value::String(self.reason.to_string()),
+                ),
+                (
+                    ron::Value::String("behavior".to_string()),
+                    self.behavior.into_ron(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
+/// A blackboard that collects the statuses of [`Traced`] behaviors as they run.
+pub trait Tracer {
+    fn trace(&mut self, label: &str, status: Status);
+}
+
+/// Reports the status of the inner behavior to the blackboard every time it runs.
+///
+/// Only behaviors wrapped in `Traced` are reported, so untraced trees pay nothing.
+pub struct Traced<A> {
+    pub label: Cow<'static, str>,
+    pub behavior: A,
+}
+
+impl<A> Traced<A> {
+    pub fn new(label: impl Into<Cow<'static, str>>, behavior: A) -> Self {
+        Self {
+            label: label.into(),
+            behavior,
+        }
+    }
+}
+
+impl<A, B> Behavior<B> for Traced<A>
+where
+    A: Behavior<B>,
+    B: Tracer,
+{
+    fn run(&mut self, blackboard: &mut B) -> Status {
+        let status = self.behavior.run(blackboard);
+        blackboard.trace(&self.label, status);
+        status
+    }
+}
+
+impl<A> CancelSafe for Traced<A>
+where
+    A: CancelSafe,
+{
+    fn reset(&mut self) {
+        self.behavior.reset();
+    }
+}
+
+impl<A> IntoRon for Traced<A>
+where
+    A: IntoRon,
+{
+    fn into_ron(&self) -> ron::Value {
+        ron::Value::Map(
+            [(
+                ron::Value::String(self.label.to_string()),
+                self.behavior.into_ron(),
+            )]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
+/// Runs a behavior on part of the blackboard, as chosen by a projection closure.
+///
+/// The projection is called on every tick to mutably borrow the part from the parent
+/// blackboard, and the borrow ends when the inner behavior returns. The inner behavior works
+/// on the parent's data directly rather than a copy, so its changes are visible to the parent
+/// as soon as it returns. Nothing can be written back later, so the inner behavior must not
+/// expect the part to stay untouched between ticks.
+pub struct WithSubBlackboard<A, F> {
+    pub behavior: A,
+    pub project: F,
+}
+
+impl<A, F> WithSubBlackboard<A, F> {
+    pub fn new<B, C>(project: F, behavior: A) -> Self
+    where
+        F: FnMut(&mut B) -> &mut C,
+    {
+        Self { behavior, project }
+    }
+}
+
+impl<A, F, B, C> Behavior<B> for WithSubBlackboard<A, F>
+where
+    A: Behavior<C>,
+    F: FnMut(&mut B) -> &mut C,
+{
+    fn run(&mut self, blackboard: &mut B) -> Status {
+        self.behavior.run((self.project)(blackboard))
+    }
+}
+
+impl<A, F> CancelSafe for WithSubBlackboard<A, F>
+where
+    A: CancelSafe,
+{
+    fn reset(&mut self) {
+        self.behavior.reset();
+    }
+}
+
+impl<A, F> IntoRon for WithSubBlackboard<A, F>
+where
+    A: IntoRon,
+{
+    fn into_ron(&self) -> ron::Value {
+        ron::Value::Map(
+            [(
+                ron::Value::String("sub_blackboard".to_string()),
+                self.behavior.into_ron(),
+            )]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
 pub struct AssertCancelSafe<A>(pub A);
 
 impl<A> CancelSafe for AssertCancelSafe<A> {
@@ -284,3 +601,170 @@ where
         self.call_mut(args)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        action::{AlwaysFail, AlwaysRunning, AlwaysSucceed},
+        sequence::{Select, Sequence},
+        Behavior, CancelSafe, FailureReason, Status,
+    };
+
+    use super::{
+        AssertCancelSafe, Once, Timed, Traced, Tracer, WithFailureReason, WithSubBlackboard,
+    };
+
+    #[test]
+    fn timed_runs_for_duration() {
+        let duration = Duration::from_millis(50);
+        let mut behavior = Timed::new(
+            |count: &mut usize| {
+                *count += 1;
+                Status::Success
+            },
+            duration,
+        );
+
+        let mut count = 0;
+        let start = Instant::now();
+        while behavior.run(&mut count) == Status::Running {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(start.elapsed() >= duration);
+        assert!(count > 1, "{count}");
+
+        // The timer restarts after finishing
+        count = 0;
+        assert_eq!(behavior.run(&mut count), Status::Running);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn sub_blackboard_changes_are_visible() {
+        struct Parent {
+            name: &'static str,
+            count: usize,
+        }
+
+        let mut behavior = WithSubBlackboard::new(
+            |parent: &mut Parent| &mut parent.count,
+            |count: &mut usize| {
+                *count += 1;
+                Status::Success
+            },
+        );
+        let mut parent = Parent {
+            name: "parent",
+            count: 0,
+        };
+        assert_eq!(behavior.run(&mut parent), Status::Success);
+        assert_eq!(behavior.run(&mut parent), Status::Success);
+        assert_eq!(parent.count, 2);
+        assert_eq!(parent.name, "parent");
+    }
+
+    #[test]
+    fn failure_reasons_propagate() {
+        let mut behavior = WithFailureReason::new(
+            "traverse",
+            Sequence::new((
+                AlwaysSucceed,
+                WithFailureReason::new("blocked", AlwaysFail),
+                AlwaysSucceed,
+            )),
+        );
+        assert_eq!(behavior.run(&mut ()), Status::failure("blocked"));
+
+        // Failures without a reason of their own take the outer reason
+        let mut behavior = WithFailureReason::new("traverse", Sequence::new((AlwaysFail,)));
+        assert_eq!(behavior.run(&mut ()), Status::failure("traverse"));
+
+        // The last child to fail gives the reason
+        let mut behavior = Select::new((
+            WithFailureReason::new("blocked", AlwaysFail),
+            WithFailureReason::new("localization lost", AlwaysFail),
+        ));
+        assert_eq!(behavior.run(&mut ()), Status::failure("localization lost"));
+
+        // Failures that a later child recovers from leave nothing behind
+        let mut behavior =
+            Select::new((WithFailureReason::new("blocked", AlwaysFail), AlwaysSucceed));
+        assert_eq!(behavior.run(&mut ()), Status::Success);
+        let mut behavior = Select::new((
+            WithFailureReason::new("blocked", AlwaysFail),
+            AlwaysSucceed,
+            AlwaysFail,
+        ));
+        assert_eq!(behavior.run(&mut ()), Status::Success);
+        let mut behavior = Select::new((WithFailureReason::new("blocked", AlwaysFail), AlwaysFail));
+        assert_eq!(
+            behavior.run(&mut ()),
+            Status::Failure(FailureReason::Unspecified)
+        );
+    }
+
+    #[test]
+    fn trace_sequence() {
+        #[derive(Default)]
+        struct Trace(Vec<(String, Status)>);
+
+        impl Tracer for Trace {
+            fn trace(&mut self, label: &str, status: Status) {
+                self.0.push((label.to_string(), status));
+            }
+        }
+
+        let mut behavior = Traced::new(
+            "sequence",
+            Sequence::new((
+                Traced::new("succeed", AlwaysSucceed),
+                AlwaysSucceed,
+                Traced::new("running", AlwaysRunning),
+            )),
+        );
+        let mut trace = Trace::default();
+        assert_eq!(behavior.run(&mut trace), Status::Running);
+        assert_eq!(
+            trace.0,
+            [
+                ("succeed".to_string(), Status::Success),
+                ("running".to_string(), Status::Running),
+                ("sequence".to_string(), Status::Running),
+            ]
+        );
+
+        // The sequence resumes from where it left off
+        trace.0.clear();
+        behavior.run(&mut trace);
+        assert_eq!(
+            trace.0,
+            [
+                ("running".to_string(), Status::Running),
+                ("sequence".to_string(), Status::Running),
+            ]
+        );
+    }
+
+    #[test]
+    fn once_runs_child_once() {
+        let mut behavior = Once::new(AssertCancelSafe(|count: &mut usize| {
+            *count += 1;
+            if *count < 2 {
+                Status::Running
+            } else {
+                Status::FAILURE
+            }
+        }));
+
+        let mut count = 0;
+        assert_eq!(behavior.run(&mut count), Status::Running);
+        assert_eq!(behavior.run(&mut count), Status::FAILURE);
+        for _ in 0..3 {
+            assert_eq!(behavior.run(&mut count), Status::FAILURE);
+            behavior.reset();
+        }
+        assert_eq!(count, 2);
+    }
+}
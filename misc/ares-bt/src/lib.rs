@@ -1,24 +1,51 @@
 #![feature(unboxed_closures, fn_traits)]
 
+use std::fmt::Display;
+
 pub mod action;
 pub mod branching;
 pub mod converters;
 pub mod looping;
 pub mod sequence;
 
+/// Why a behavior failed, which is carried by failures up through every combinator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum FailureReason {
+    /// The behavior did not say why it failed.
+    #[default]
+    Unspecified,
+    Because(&'static str),
+}
+
+impl Display for FailureReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unspecified => write!(f, "unspecified"),
+            Self::Because(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Status {
     Running,
     Success,
-    Failure,
+    Failure(FailureReason),
 }
 
 impl Status {
+    /// A failure without a reason.
+    pub const FAILURE: Self = Self::Failure(FailureReason::Unspecified);
+
+    pub const fn failure(reason: &'static str) -> Self {
+        Self::Failure(FailureReason::Because(reason))
+    }
+
     pub const fn is_ok(self) -> bool {
         match self {
             Self::Running => false,
             Self::Success => true,
-            Self::Failure => false,
+            Self::Failure(_) => false,
         }
     }
 
@@ -26,7 +53,7 @@ impl Status {
         match self {
             Self::Running => false,
             Self::Success => false,
-            Self::Failure => true,
+            Self::Failure(_) => true,
         }
     }
 
@@ -34,7 +61,7 @@ impl Status {
         match self {
             Self::Running => true,
             Self::Success => false,
-            Self::Failure => false,
+            Self::Failure(_) => false,
         }
     }
 }
@@ -44,7 +71,7 @@ impl From<bool> for Status {
         if value {
             Status::Success
         } else {
-            Status::Failure
+            Status::FAILURE
         }
     }
 }
@@ -52,28 +79,35 @@ impl From<bool> for Status {
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum FallibleStatus {
     Running,
-    Failure,
+    Failure(FailureReason),
 }
 
 impl FallibleStatus {
+    /// A failure without a reason.
+    pub const FAILURE: Self = Self::Failure(FailureReason::Unspecified);
+
+    pub const fn failure(reason: &'static str) -> Self {
+        Self::Failure(FailureReason::Because(reason))
+    }
+
     pub const fn is_ok(self) -> bool {
         match self {
             Self::Running => false,
-            Self::Failure => false,
+            Self::Failure(_) => false,
         }
     }
 
     pub const fn is_err(self) -> bool {
         match self {
             Self::Running => false,
-            Self::Failure => true,
+            Self::Failure(_) => true,
         }
     }
 
     pub const fn is_running(self) -> bool {
         match self {
             Self::Running => true,
-            Self::Failure => false,
+            Self::Failure(_) => false,
         }
     }
 }
@@ -173,7 +207,7 @@ impl From<FallibleStatus> for Status {
     fn from(value: FallibleStatus) -> Self {
         match value {
             FallibleStatus::Running => Status::Running,
-            FallibleStatus::Failure => Status::Failure,
+            FallibleStatus::Failure(reason) => Status::Failure(reason),
         }
     }
 }
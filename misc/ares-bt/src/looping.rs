@@ -40,15 +40,15 @@ where
                 match self.condition.run(blackboard) {
                     Status::Running => return Status::Running,
                     Status::Success => self.check_condition = false,
-                    Status::Failure => return Status::Success,
+                    Status::Failure(_) => return Status::Success,
                 }
             }
             match self.body.run(blackboard) {
                 Status::Running => return Status::Running,
                 Status::Success => self.check_condition = true,
-                Status::Failure => {
+                Status::Failure(reason) => {
                     self.check_condition = true;
-                    return Status::Failure;
+                    return Status::Failure(reason);
                 }
             }
         }
@@ -82,9 +82,9 @@ where
             }
             match self.body.run_fallible(blackboard) {
                 FallibleStatus::Running => return FallibleStatus::Running,
-                FallibleStatus::Failure => {
+                FallibleStatus::Failure(reason) => {
                     self.check_condition = true;
-                    return FallibleStatus::Failure;
+                    return FallibleStatus::Failure(reason);
                 }
             }
         }
@@ -102,7 +102,7 @@ where
                 match self.condition.run(blackboard) {
                     Status::Running => return InfallibleStatus::Running,
                     Status::Success => self.check_condition = false,
-                    Status::Failure => return InfallibleStatus::Success,
+                    Status::Failure(_) => return InfallibleStatus::Success,
                 }
             }
             match self.body.run_infallible(blackboard) {
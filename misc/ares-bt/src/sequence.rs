@@ -1,6 +1,6 @@
 use crate::{
-    Behavior, CancelSafe, EternalBehavior, EternalStatus, FallibleBehavior, FallibleStatus,
-    InfallibleBehavior, InfallibleStatus, IntoRon, Status,
+    Behavior, CancelSafe, EternalBehavior, EternalStatus, FailureReason, FallibleBehavior,
+    FallibleStatus, InfallibleBehavior, InfallibleStatus, IntoRon, Status,
 };
 
 pub struct Sequence<A> {
@@ -23,9 +23,9 @@ macro_rules! impl_seq {
                                 Status::Success => {
                                     self.index += 1;
                                 }
-                                Status::Failure => {
+                                Status::Failure(reason) => {
                                     self.index = 0;
-                                    return Status::Failure;
+                                    return Status::Failure(reason);
                                 }
                             }
                         )+
@@ -137,6 +137,8 @@ macro_rules! impl_sel {
             $($name: Behavior<C1>,)+
         {
             fn run(&mut self, blackboard: &mut C1) -> Status {
+                // Only the last child to fail gives the reason, so earlier failures are never stale
+                let mut reason = FailureReason::Unspecified;
                 loop {
                     match self.index {
                         $(
@@ -146,14 +148,15 @@ macro_rules! impl_sel {
                                     self.index = 0;
                                     return Status::Success;
                                 }
-                                Status::Failure => {
+                                Status::Failure(failure) => {
                                     self.index += 1;
+                                    reason = failure;
                                 }
                             }
                         )+
                         _ => {
                             self.index = 0;
-                            return Status::Failure;
+                            return Status::Failure(reason);
                         }
                     }
                 }
@@ -184,19 +187,22 @@ macro_rules! impl_sel {
             $($name: FallibleBehavior<C1>,)+
         {
             fn run_fallible(&mut self, blackboard: &mut C1) -> FallibleStatus {
+                // Only the last child to fail gives the reason, so earlier failures are never stale
+                let mut reason = FailureReason::Unspecified;
                 loop {
                     match self.index {
                         $(
                             $num => match self.body.$num.run_fallible(blackboard) {
                                 FallibleStatus::Running => return FallibleStatus::Running,
-                                FallibleStatus::Failure => {
+                                FallibleStatus::Failure(failure) => {
                                     self.index += 1;
+                                    reason = failure;
                                 }
                             }
                         )+
                         _ => {
                             self.index = 0;
-                            return FallibleStatus::Failure;
+                            return FallibleStatus::Failure(reason);
                         }
                     }
                 }
@@ -278,9 +284,9 @@ macro_rules! impl_seq {
                                         return Status::Success;
                                     }
                                 }
-                                Status::Failure => {
+                                Status::Failure(reason) => {
                                     self.reset();
-                                    return Status::Failure;
+                                    return Status::Failure(reason);
                                 }
                             }
                         )+
@@ -348,9 +354,9 @@ macro_rules! impl_seq {
                                     self.index += 1;
                                     return FallibleStatus::Running;
                                 }
-                                FallibleStatus::Failure => {
+                                FallibleStatus::Failure(reason) => {
                                     self.reset();
-                                    return FallibleStatus::Failure;
+                                    return FallibleStatus::Failure(reason);
                                 }
                             }
                         )+
@@ -449,13 +455,13 @@ macro_rules! impl_sel {
                                     self.reset();
                                     return Status::Success;
                                 }
-                                Status::Failure => {
+                                Status::Failure(reason) => {
                                     self.index += 1;
                                     self.failed += 1;
                                     if self.failed == $len {
                                         self.index = 0;
                                         self.failed = 0;
-                                        return Status::Failure;
+                                        return Status::Failure(reason);
                                     }
                                 }
                             }
@@ -519,13 +525,13 @@ macro_rules! impl_sel {
                                     self.index += 1;
                                     return FallibleStatus::Running;
                                 },
-                                FallibleStatus::Failure => {
+                                FallibleStatus::Failure(reason) => {
                                     self.index += 1;
                                     self.failed += 1;
                                     if self.failed == $len {
                                         self.index = 0;
                                         self.failed = 0;
-                                        return FallibleStatus::Failure;
+                                        return FallibleStatus::Failure(reason);
                                     }
                                 }
                             }
@@ -624,9 +630,9 @@ macro_rules! impl_sel {
                                     self.reset();
                                     return Status::Success;
                                 }
-                                Status::Failure => {
+                                Status::Failure(reason) => {
                                     self.reset();
-                                    return Status::Failure;
+                                    return Status::Failure(reason);
                                 }
                             }
                         )+
@@ -689,9 +695,9 @@ macro_rules! impl_sel {
                                     self.index += 1;
                                     return FallibleStatus::Running;
                                 },
-                                FallibleStatus::Failure => {
+                                FallibleStatus::Failure(reason) => {
                                     self.reset();
-                                    return FallibleStatus::Failure;
+                                    return FallibleStatus::Failure(reason);
                                 }
                             }
                         )+
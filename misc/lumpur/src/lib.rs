@@ -18,6 +18,7 @@ use cursive::views::{
     Button, HideableView, Layer, LinearLayout, NamedView, ScrollView, TextView, ThemedView,
 };
 use cursive::Cursive;
+pub use log::recent_logs;
 use log::{log_write_thread, make_line_f, LogMessage};
 use raw_sync::events::{EventInit, EventState};
 use regex::RegexSet;
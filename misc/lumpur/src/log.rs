@@ -1,16 +1,100 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, VecDeque},
     io::{LineWriter, Write},
     path::{Path, PathBuf},
     sync::{
         mpsc::{Receiver, Sender},
-        Arc,
+        Arc, Mutex, PoisonError,
     },
 };
 
 use regex::RegexSet;
 use tracing::Level;
 
+/// How many of the most recent log records are kept in memory.
+const LOG_RING_CAPACITY: usize = 256;
+/// Where the most recent log records are appended when this process panics.
+pub(crate) const CRASH_LOG_PATH: &str = "crash.log";
+
+pub(crate) static LOG_RING: Mutex<LogRing> = Mutex::new(LogRing::new(LOG_RING_CAPACITY));
+
+/// The most recent log records, oldest first.
+pub(crate) struct LogRing {
+    records: VecDeque<String>,
+    capacity: usize,
+}
+
+impl LogRing {
+    pub(crate) const fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub(crate) fn push(&mut self, record: String) {
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<String> {
+        self.records.iter().cloned().collect()
+    }
+}
+
+/// Returns the most recent log records of this process, oldest first, as JSON lines.
+///
+/// This is meant for sending the context of a critical failure somewhere other than the log
+/// file. A panic already appends these to [`CRASH_LOG_PATH`]. Only the process running the
+/// app records logs, so this is empty elsewhere.
+pub fn recent_logs() -> Vec<String> {
+    LOG_RING.lock().unwrap().to_vec()
+}
+
+/// Writes the most recent log records, oldest first, one per line.
+///
+/// This is called from the panic hook, so a lock poisoned by another panic is ignored.
+pub(crate) fn dump_recent_logs(mut writer: impl Write) -> std::io::Result<()> {
+    let ring = LOG_RING.lock().unwrap_or_else(PoisonError::into_inner);
+    for record in &ring.records {
+        writeln!(writer, "{record}")?;
+    }
+    writer.flush()
+}
+
+/// Writes a log record to stderr, and keeps a copy in [`LOG_RING`].
+///
+/// Every record is copied into a new `String` and pushed under the global lock of
+/// [`LOG_RING`] on each log call, whether or not the records are ever read.
+#[derive(Default)]
+pub(crate) struct RingWriter {
+    buffer: Vec<u8>,
+}
+
+impl Write for RingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RingWriter {
+    fn drop(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let _ = std::io::stderr().write_all(&self.buffer);
+        let record = String::from_utf8_lossy(&self.buffer).trim_end().to_string();
+        LOG_RING.lock().unwrap().push(record);
+    }
+}
+
 #[derive(serde::Deserialize, Clone, Copy, Debug)]
 enum SerdeLevel {
     ERROR,
@@ -223,3 +307,28 @@ pub(crate) fn log_write_thread(
     }
     let _ = log_file.flush();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{dump_recent_logs, LogRing, LOG_RING};
+
+    #[test]
+    fn log_ring_keeps_most_recent() {
+        let mut ring = LogRing::new(3);
+        for i in 0..5 {
+            ring.push(i.to_string());
+        }
+        assert_eq!(ring.to_vec(), ["2", "3", "4"]);
+    }
+
+    #[test]
+    fn recent_logs_are_dumped() {
+        LOG_RING.lock().unwrap().push("first".into());
+        LOG_RING.lock().unwrap().push("second".into());
+        let mut dump = vec![];
+        dump_recent_logs(&mut dump).unwrap();
+        assert!(String::from_utf8(dump)
+            .unwrap()
+            .ends_with("first\nsecond\n"));
+    }
+}
@@ -1,11 +1,16 @@
-use std::{backtrace::Backtrace, panic::set_hook, path::Path, sync::Mutex};
+use std::{
+    backtrace::Backtrace, fs::OpenOptions, io::BufWriter, panic::set_hook, path::Path, sync::Mutex,
+};
 
 use raw_sync::{events::EventInit, Timeout};
 use shared_memory::ShmemConf;
 use tracing::Level;
 use tracing_subscriber::fmt::time::Uptime;
 
-use crate::config::Configuration;
+use crate::{
+    config::Configuration,
+    log::{dump_recent_logs, RingWriter, CRASH_LOG_PATH},
+};
 
 pub(crate) const EMBEDDED_KEY: &str = "__LUMPUR_EMBEDDED";
 pub(crate) const EMBEDDED_VAL: &str = "1";
@@ -28,7 +33,7 @@ pub(crate) fn subprocess_fn<C: Configuration>() -> C {
         .with_thread_names(true)
         .with_timer(Uptime::default())
         .with_max_level(Level::TRACE)
-        .with_writer(std::io::stderr)
+        .with_writer(RingWriter::default)
         .finish();
 
     tracing::subscriber::set_global_default(sub)
@@ -37,6 +42,16 @@ pub(crate) fn subprocess_fn<C: Configuration>() -> C {
     set_hook(Box::new(move |info| {
         let backtrace = Backtrace::capture();
         tracing::error!("{info}\n{backtrace}");
+        // The parent process writes the log file, and may not receive the last records if this
+        // process is about to die, so they are also kept next to it
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(CRASH_LOG_PATH)
+            .and_then(|file| dump_recent_logs(BufWriter::new(file)));
+        if let Err(e) = result {
+            eprintln!("Failed to write {CRASH_LOG_PATH}: {e}");
+        }
     }));
 
     let flink = std::env::var(SHMEM_VAR_KEY).unwrap();
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use crossbeam::queue::{ArrayQueue, SegQueue};
 use tokio::sync::Notify;
@@ -56,6 +56,11 @@ struct SubscriberInner<T> {
     notify: Notify,
 }
 
+/// A queue of values that are sent through callbacks.
+///
+/// Every `Subscriber` owns its own queue, so when the same values are sent to several
+/// subscribers, one that is slow or never read from only fills its own queue. Neither the
+/// caller nor the other subscribers ever wait on it.
 pub struct Subscriber<T> {
     inner: Arc<SubscriberInner<T>>,
 }
@@ -109,6 +114,21 @@ impl<T> Subscriber<T> {
         }
     }
 
+    /// Receives a value, blocking until a value is available or `deadline` has passed.
+    ///
+    /// Returns `None` once `deadline` has passed, even if values are still queued, or if
+    /// the subscriber is closed. Calling this in a loop drains every value that arrives
+    /// before the deadline without waiting for any that arrive after it.
+    pub async fn recv_until_deadline(&self, deadline: Instant) -> Option<T> {
+        if Instant::now() >= deadline {
+            return None;
+        }
+        tokio::time::timeout_at(deadline.into(), self.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
     /// Receives a value, blocking until a value is available, or
     /// blocking forever if the subscriber is closed.
     ///
@@ -173,3 +193,61 @@ impl<T> Subscriber<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Subscriber;
+
+    crate::define_callbacks!(TestCallbacks => Fn(value: u32) + Send + Sync);
+
+    #[test]
+    fn stalled_subscriber_does_not_block_others() {
+        let mut callbacks = TestCallbacks::default();
+        let fast = Subscriber::new(2);
+        let stalled = Subscriber::new(2);
+        let callbacks_ref = callbacks.get_ref();
+        callbacks_ref.add_dyn_fn(Box::new(fast.create_callback()));
+        callbacks_ref.add_dyn_fn(Box::new(stalled.create_callback()));
+
+        let mut received = vec![];
+        for i in 0..10 {
+            callbacks.call(i);
+            received.extend(std::iter::from_fn(|| fast.try_recv()));
+        }
+        // The fast subscriber sees every value even though the stalled one was full after two
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+        // The stalled subscriber only kept the newest values that fit in its own queue
+        assert_eq!(stalled.try_recv(), Some(8));
+        assert_eq!(stalled.try_recv(), Some(9));
+        assert_eq!(stalled.try_recv(), None);
+    }
+
+    #[tokio::test]
+    async fn recv_until_deadline_stops_while_messages_keep_coming() {
+        let subscriber = Subscriber::new_unbounded();
+        let callback = subscriber.create_callback();
+        tokio::spawn(async move {
+            for i in 0u32.. {
+                callback(i);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let mut received = vec![];
+        while let Some(value) = subscriber.recv_until_deadline(deadline).await {
+            received.push(value);
+        }
+        let stopped_at = Instant::now();
+        assert!(stopped_at >= deadline);
+        assert!(stopped_at < deadline + Duration::from_millis(50));
+        assert!(received.len() > 1);
+        assert!(received.windows(2).all(|w| w[1] == w[0] + 1));
+
+        // Messages that arrive after the deadline are left for the next receive
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(subscriber.try_recv(), Some(received.last().unwrap() + 1));
+    }
+}
@@ -4,14 +4,22 @@ build_shader!(
     pub(crate) Depth2Pcl,
     r#"
 #[buffer] var<storage, read> depths: array<u32, HALF_PIXEL_COUNT>;
-#[buffer] var<storage, read_write> points: array<vec4f, PIXEL_COUNT>;
+#[buffer] var<storage, read_write> points: array<vec4f, POINT_COUNT>;
 #[buffer] var<uniform> transform: mat4x4f;
 #[buffer] var<uniform> depth_scale: f32;
 
 const IMAGE_WIDTH: NonZeroU32 = {{image_width}};
+const OUTPUT_WIDTH: NonZeroU32 = {{output_width}};
+const OUTPUT_HEIGHT: NonZeroU32 = {{output_height}};
+const DECIMATION: NonZeroU32 = {{decimation}};
 const FOCAL_LENGTH_PX: f32 = {{focal_length_px}};
 const PRINCIPAL_POINT_PX: vec2f = {{principal_point_px}};
-const PIXEL_COUNT: NonZeroU32 = {{pixel_count}};
+const Y_SIGN: f32 = {{y_sign}};
+const PLANAR_DEPTH: bool = {{planar_depth}};
+const MIN_DEPTH: f32 = {{min_depth}};
+const HAS_MAX_DEPTH: bool = {{has_max_depth}};
+const MAX_DEPTH: f32 = {{max_depth}};
+const POINT_COUNT: NonZeroU32 = {{point_count}};
 const HALF_PIXEL_COUNT: NonZeroU32 = {{half_pixel_count}};
 
 @compute
@@ -19,13 +27,15 @@ const HALF_PIXEL_COUNT: NonZeroU32 = {{half_pixel_count}};
 fn depth(
     @builtin(global_invocation_id) global_invocation_id : vec3u,
 ) {
-    let i = global_invocation_id.x + global_invocation_id.y * IMAGE_WIDTH;
-    if i >= PIXEL_COUNT {
+    if global_invocation_id.x >= OUTPUT_WIDTH || global_invocation_id.y >= OUTPUT_HEIGHT {
         return;
     }
-    let double_depth = depths[i / 2];
+    let i = global_invocation_id.x + global_invocation_id.y * OUTPUT_WIDTH;
+    let pixel = global_invocation_id.xy * DECIMATION;
+    let j = pixel.x + pixel.y * IMAGE_WIDTH;
+    let double_depth = depths[j / 2];
     var depthu: u32;
-    if i % 2 == 1 {
+    if j % 2 == 1 {
         depthu = double_depth >> 16;
     } else {
         depthu = double_depth & 0xFFFF;
@@ -37,10 +47,19 @@ fn depth(
     }
 
     let depth = f32(depthu) * depth_scale;
-    let x = (f32(global_invocation_id.x) - PRINCIPAL_POINT_PX.x) / FOCAL_LENGTH_PX;
-    let y = (f32(global_invocation_id.y) - PRINCIPAL_POINT_PX.y) / FOCAL_LENGTH_PX;
+    if depth < MIN_DEPTH || (HAS_MAX_DEPTH && depth > MAX_DEPTH) {
+        points[i].w = 0.0;
+        return;
+    }
+    let x = (f32(pixel.x) - PRINCIPAL_POINT_PX.x) / FOCAL_LENGTH_PX;
+    let y = (f32(pixel.y) - PRINCIPAL_POINT_PX.y) / FOCAL_LENGTH_PX * Y_SIGN;
 
-    let point = normalize(vec3(x, y, -1)) * depth;
+    var point: vec3f;
+    if PLANAR_DEPTH {
+        point = vec3(x, y, -1.0) * depth;
+    } else {
+        point = normalize(vec3(x, y, -1.0)) * depth;
+    }
     var point_transformed = transform * vec4<f32>(point, 1.0);
     point_transformed.w = 1.0;
     points[i] = point_transformed;
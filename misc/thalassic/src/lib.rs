@@ -97,37 +97,121 @@ type BetaBindGroups = (
     GpuBufferSet<ExpanderBindGrp>,
 );
 
+/// How a pixel and its depth are turned into a point local to the camera.
+///
+/// In every convention, +X is towards the right of the image and the camera looks down -Z.
+/// The resulting point is then transformed by the camera transform given to [`DepthProjector::project`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DepthConvention {
+    /// +Y is towards the bottom of the image, and depth is the distance along the ray
+    /// through the pixel.
+    ///
+    /// This is what lunasim produces, and what the RealSense cameras are projected with.
+    #[default]
+    YDownRayDistance,
+    /// +Y is towards the top of the image, and depth is the distance along -Z.
+    ///
+    /// This is a right-handed frame matching OpenGL.
+    YUpPlanar,
+}
+
+impl DepthConvention {
+    fn y_sign(self) -> f32 {
+        match self {
+            Self::YDownRayDistance => 1.0,
+            Self::YUpPlanar => -1.0,
+        }
+    }
+
+    fn is_planar(self) -> bool {
+        match self {
+            Self::YDownRayDistance => false,
+            Self::YUpPlanar => true,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct DepthProjectorBuilder {
     pub image_size: Vector2<NonZeroU32>,
     pub focal_length_px: f32,
     pub principal_point_px: Vector2<f32>,
+    pub convention: DepthConvention,
+    /// Depths closer than this many meters are marked invalid.
+    pub min_depth: Option<f32>,
+    /// Depths further than this many meters are marked invalid.
+    pub max_depth: Option<f32>,
+    /// Only every Nth pixel in each dimension of the depth image is projected.
+    ///
+    /// A decimation of 1 projects every pixel.
+    pub decimation: NonZeroU32,
 }
 
 impl DepthProjectorBuilder {
+    /// Returns `true` if a pixel with the given depth in meters will be projected.
+    ///
+    /// Pixels with no depth (a raw depth of `0`) are never projected. Points for pixels
+    /// that are not projected have a `w` of `0.0`.
+    pub fn is_depth_valid(&self, depth: f32) -> bool {
+        depth > 0.0
+            && self.min_depth.is_none_or(|min| depth >= min)
+            && self.max_depth.is_none_or(|max| depth <= max)
+    }
+
+    /// Projects a single pixel with the given depth in meters into the camera's local space,
+    /// exactly as the GPU projection does before the camera transform is applied.
+    pub fn project_pixel(&self, pixel: Vector2<u32>, depth: f32) -> Vector3<f32> {
+        let x = (pixel.x as f32 - self.principal_point_px.x) / self.focal_length_px;
+        let y = (pixel.y as f32 - self.principal_point_px.y) / self.focal_length_px
+            * self.convention.y_sign();
+        let ray = Vector3::new(x, y, -1.0);
+        if self.convention.is_planar() {
+            ray * depth
+        } else {
+            ray.normalize() * depth
+        }
+    }
+
+    /// The dimensions of the point cloud after decimation.
+    pub fn output_size(&self) -> Vector2<NonZeroU32> {
+        self.image_size
+            .map(|n| NonZeroU32::new(n.get().div_ceil(self.decimation.get())).unwrap())
+    }
+
     pub fn build(self) -> DepthProjector {
         let pixel_count = self.image_size.x.get() * self.image_size.y.get();
+        let output_size = self.output_size();
+        let point_count = output_size.x.get() * output_size.y.get();
         let [depth_fn] = Depth2Pcl {
             depths: BufferGroupBinding::<_, AlphaBindGroups>::get::<0, 0>(),
             points: BufferGroupBinding::<_, AlphaBindGroups>::get::<1, 0>(),
             transform: BufferGroupBinding::<_, AlphaBindGroups>::get::<0, 1>(),
             depth_scale: BufferGroupBinding::<_, AlphaBindGroups>::get::<0, 2>(),
             image_width: self.image_size.x,
+            output_width: output_size.x,
+            output_height: output_size.y,
+            decimation: self.decimation,
             focal_length_px: self.focal_length_px,
             principal_point_px: self.principal_point_px.into(),
-            pixel_count: NonZeroU32::new(pixel_count).unwrap(),
+            y_sign: self.convention.y_sign(),
+            planar_depth: self.convention.is_planar(),
+            min_depth: self.min_depth.unwrap_or(0.0),
+            has_max_depth: self.max_depth.is_some(),
+            max_depth: self.max_depth.unwrap_or(0.0),
+            point_count: NonZeroU32::new(point_count).unwrap(),
             half_pixel_count: NonZeroU32::new(pixel_count.div_ceil(2)).unwrap(),
         }
         .compile();
 
         let mut pipeline = ComputePipeline::new([&depth_fn]);
         pipeline.workgroups = [Vector3::new(
-            self.image_size.x.get() / 8,
-            self.image_size.y.get() / 8,
+            output_size.x.get().div_ceil(8),
+            output_size.y.get().div_ceil(8),
             1,
         )];
         DepthProjector {
             image_size: self.image_size,
+            output_size,
             pipeline,
             bind_grp: Some(GpuBufferSet::from((
                 StorageBuffer::new_dyn(pixel_count.div_ceil(2) as usize).unwrap(),
@@ -138,15 +222,14 @@ impl DepthProjectorBuilder {
     }
 
     pub fn make_points_storage(self) -> PointCloudStorage {
+        let output_size = self.output_size();
         PointCloudStorage {
             points_grp: GpuBufferSet::from((
-                StorageBuffer::new_dyn(
-                    self.image_size.x.get() as usize * self.image_size.y.get() as usize,
-                )
-                .unwrap(),
+                StorageBuffer::new_dyn(output_size.x.get() as usize * output_size.y.get() as usize)
+                    .unwrap(),
                 UniformBuffer::new(),
             )),
-            image_size: self.image_size,
+            image_size: output_size,
         }
     }
 }
@@ -168,6 +251,7 @@ impl PointCloudStorage {
 
 pub struct DepthProjector {
     image_size: Vector2<NonZeroU32>,
+    output_size: Vector2<NonZeroU32>,
     pipeline: ComputePipeline<AlphaBindGroups, 1>,
     bind_grp: Option<GpuBufferSet<DepthBindGrp>>,
 }
@@ -180,7 +264,7 @@ impl DepthProjector {
         mut points_storage: PointCloudStorage,
         depth_scale: f32,
     ) -> PointCloudStorage {
-        debug_assert_eq!(self.image_size, points_storage.image_size);
+        debug_assert_eq!(self.output_size, points_storage.image_size);
         debug_assert_eq!(
             depths.len(),
             self.image_size.x.get() as usize * self.image_size.y.get() as usize
@@ -201,7 +285,7 @@ impl DepthProjector {
                 bind_grps.0.write::<2, _>(&depth_scale, &mut lock);
                 bind_grps
                     .1
-                    .write::<1, _>(&self.image_size.x.get(), &mut lock);
+                    .write::<1, _>(&self.output_size.x.get(), &mut lock);
                 &mut bind_grps
             })
             .finish();
@@ -215,8 +299,15 @@ impl DepthProjector {
         self.image_size
     }
 
+    /// The dimensions of the point cloud after decimation.
+    pub fn get_output_size(&self) -> Vector2<NonZeroU32> {
+        self.output_size
+    }
+
+    /// The number of points produced per projection, which is the number of pixels
+    /// after decimation.
     pub fn get_pixel_count(&self) -> NonZeroU32 {
-        NonZeroU32::new(self.image_size.x.get() * self.image_size.y.get()).unwrap()
+        NonZeroU32::new(self.output_size.x.get() * self.output_size.y.get()).unwrap()
     }
 }
 
@@ -310,6 +401,8 @@ pub struct Occupancy(u32);
 
 impl Occupancy {
     pub const FREE: Self = Self(0);
+    /// A cell that is itself an obstacle, as opposed to one that is only near an obstacle.
+    pub const OCCUPIED: Self = Self(1);
 
     pub fn occupied(self) -> bool {
         self.0 != 0
@@ -477,3 +570,127 @@ impl ThalassicPipeline {
         self.new_radius = Some(radius);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Matrix4;
+
+    use super::*;
+
+    fn builder(convention: DepthConvention) -> DepthProjectorBuilder {
+        DepthProjectorBuilder {
+            image_size: Vector2::new(NonZeroU32::new(8).unwrap(), NonZeroU32::new(6).unwrap()),
+            focal_length_px: 2.0,
+            principal_point_px: Vector2::new(4.0, 3.0),
+            convention,
+            min_depth: None,
+            max_depth: None,
+            decimation: NonZeroU32::MIN,
+        }
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn depth_range_mask() {
+        let mut builder = builder(DepthConvention::YDownRayDistance);
+        let depths = [0u16, 50, 100, 2000, 0, 600];
+        let depth_scale = 0.001;
+        let valid = |builder: &DepthProjectorBuilder| -> Vec<bool> {
+            depths
+                .iter()
+                .map(|&depth| builder.is_depth_valid(depth as f32 * depth_scale))
+                .collect()
+        };
+
+        assert_eq!(valid(&builder), [false, true, true, true, false, true]);
+
+        builder.min_depth = Some(0.1);
+        builder.max_depth = Some(1.0);
+        assert_eq!(valid(&builder), [false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn principal_point_projects_forward() {
+        for convention in [
+            DepthConvention::YDownRayDistance,
+            DepthConvention::YUpPlanar,
+        ] {
+            assert_close(
+                builder(convention).project_pixel(Vector2::new(4, 3), 2.5),
+                Vector3::new(0.0, 0.0, -2.5),
+            );
+        }
+    }
+
+    #[test]
+    fn ray_distance_convention() {
+        // One focal length right and down of the principal point
+        let point =
+            builder(DepthConvention::YDownRayDistance).project_pixel(Vector2::new(6, 5), 3.0);
+        assert_close(point, Vector3::new(1.0, 1.0, -1.0).normalize() * 3.0);
+        assert!((point.magnitude() - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn planar_convention() {
+        let point = builder(DepthConvention::YUpPlanar).project_pixel(Vector2::new(6, 5), 3.0);
+        assert_close(point, Vector3::new(3.0, -3.0, -3.0));
+    }
+
+    #[test]
+    fn gpu_projection_matches_project_pixel() {
+        gputter::init_gputter_blocking().unwrap();
+        let pixel = Vector2::new(6, 5);
+        let mut depths = [0u16; 48];
+        depths[(pixel.x + pixel.y * 8) as usize] = 3000;
+        // The camera is 1 meter up
+        let camera_transform: AlignedMatrix4<f32> =
+            Matrix4::new_translation(&Vector3::new(0.0, 1.0, 0.0)).into();
+
+        for convention in [
+            DepthConvention::YDownRayDistance,
+            DepthConvention::YUpPlanar,
+        ] {
+            let builder = builder(convention);
+            let points_storage = builder.build().project(
+                &depths,
+                &camera_transform,
+                builder.make_points_storage(),
+                0.001,
+            );
+            let mut points = [AlignedVec4::default(); 48];
+            points_storage.read(&mut points);
+
+            for (i, point) in points.into_iter().enumerate() {
+                let point = Vector4::from(point);
+                if i == (pixel.x + pixel.y * 8) as usize {
+                    assert_eq!(point.w, 1.0);
+                    assert_close(
+                        point.xyz(),
+                        builder.project_pixel(pixel, 3.0) + Vector3::new(0.0, 1.0, 0.0),
+                    );
+                } else {
+                    assert_eq!(point.w, 0.0, "{convention:?} {i}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decimated_point_count() {
+        let mut builder = builder(DepthConvention::YDownRayDistance);
+        assert_eq!(builder.output_size(), builder.image_size);
+
+        builder.decimation = NonZeroU32::new(2).unwrap();
+        let size = builder.output_size();
+        assert_eq!((size.x.get(), size.y.get()), (4, 3));
+
+        // Partial blocks at the edges still produce a point
+        builder.decimation = NonZeroU32::new(3).unwrap();
+        let size = builder.output_size();
+        assert_eq!((size.x.get(), size.y.get()), (3, 2));
+    }
+}